@@ -0,0 +1,73 @@
+//! Throughput of the sequential and the chunked parallel CSV conversion.
+//!
+//! `cargo bench --bench csv_convert` generates a 1 GiB CSV in the temp directory (kept for later runs)
+//! and converts it to JSON and CSV. Set `RCLI_BENCH_BYTES` for another input size
+//! and `RCLI_BENCH_JOBS` for the number of threads of the parallel run, one per CPU by default.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use clap::Parser;
+use rcli::{convert_csv, convert_csv_parallel, CsvOpts, CHUNK_SIZE};
+
+const DEFAULT_BYTES: u64 = 1024 * 1024 * 1024;
+
+fn generate(path: &PathBuf, bytes: u64) -> io::Result<()> {
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() >= bytes) {
+        return Ok(());
+    }
+    eprintln!("generating {} ...", path.display());
+    let mut writer = BufWriter::new(File::create(path)?);
+    let mut written = 0;
+    writer.write_all(b"Name,Position,DOB,Nationality,Kit Number,Note\n")?;
+    let mut i = 0u64;
+    while written < bytes {
+        let line = format!(
+            "Player {i},Midfield,\"Jan {}, 1990 (29)\",Country {},{},\"said \"\"hi\"\"\nand left\"\n",
+            i % 28 + 1,
+            i % 50,
+            i % 99
+        );
+        writer.write_all(line.as_bytes())?;
+        written += line.len() as u64;
+        i += 1;
+    }
+    writer.flush()
+}
+
+fn run(path: &PathBuf, format: &str, jobs: &str) -> anyhow::Result<f64> {
+    let opts = CsvOpts::parse_from(["csv", "-i", "-", "--format", format, "--jobs", jobs]);
+    let input = File::open(path)?;
+    let start = Instant::now();
+    if jobs == "1" {
+        convert_csv(input, io::sink(), &opts)?;
+    } else {
+        convert_csv_parallel(input, io::sink(), &opts, CHUNK_SIZE)?;
+    }
+    Ok(start.elapsed().as_secs_f64())
+}
+
+fn main() -> anyhow::Result<()> {
+    let bytes = env::var("RCLI_BENCH_BYTES").map_or(Ok(DEFAULT_BYTES), |bytes| bytes.parse())?;
+    let jobs = env::var("RCLI_BENCH_JOBS").unwrap_or_else(|_| "0".into());
+    let path = env::temp_dir().join(format!("rcli-bench-{}.csv", bytes));
+    generate(&path, bytes)?;
+    let mb = fs::metadata(&path)?.len() as f64 / (1024.0 * 1024.0);
+
+    for format in ["json", "csv"] {
+        let sequential = run(&path, format, "1")?;
+        let parallel = run(&path, format, &jobs)?;
+        println!(
+            "{format:>4}: sequential {:8.1} MiB/s, parallel {:8.1} MiB/s, speedup {:.2}x",
+            mb / sequential,
+            mb / parallel,
+            sequential / parallel
+        );
+    }
+    Ok(())
+}
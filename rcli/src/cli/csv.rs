@@ -1,6 +1,7 @@
 use std::{ fmt, str::FromStr};
 
-use clap::Parser;
+use clap::{ArgAction, Args, Parser, Subcommand};
+use encoding_rs::Encoding;
 
 use super::verify_file;
 
@@ -8,42 +9,377 @@ use super::verify_file;
 pub enum OutputFormat {
     Json,
     Yaml,
-    // Toml,
+    Toml,
+    Ndjson,
+    Csv,
+    Md,
+    Html,
+    Parquet,
+    Arrow,
+}
+
+/// How `rcli csv join` treats rows without a match on the other side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Outer,
+}
+
+/// Character encoding of the CSV input, transcoded to UTF-8 before parsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEncoding {
+    Auto,
+    Label(&'static Encoding),
+}
+
+/// Type of a CSV cell value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellType {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+/// `rcli csv -i input.csv` converts, `rcli csv <COMMAND>` runs one of the other CSV tools
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct CsvCommand {
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCommand>,
+
+    #[command(flatten)]
+    pub convert: Option<CsvOpts>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CsvSubCommand {
+    #[command(about = "Show CSV as a table in the terminal")]
+    Show(CsvShowOpts),
+
+    #[command(about = "Profile the columns of a CSV file")]
+    Stats(CsvStatsOpts),
+
+    #[command(about = "Check a CSV file against a column spec")]
+    Validate(CsvValidateOpts),
+
+    #[command(about = "Compare two CSV files row by row on key columns")]
+    Diff(CsvDiffOpts),
+
+    #[command(about = "Join two CSV files on key columns")]
+    Join(CsvJoinOpts),
+
+    #[command(about = "Concatenate CSV files, reconciling their headers")]
+    Cat(CsvCatOpts),
 }
 
 #[derive(Debug, Parser)]
 pub struct CsvOpts {
-    /// Input file
-    #[arg(short, long, value_name = "INPUT", value_parser = verify_file)]
+    /// Input file, '-' for stdin. Spreadsheets (.xlsx, .xlsm, .xlsb, .xls, .ods) are read by extension
+    // clap leaves the group of a struct with flattened fields empty, so `Option<CsvOpts>`
+    // would always be None without adding the input to it explicitly
+    #[arg(short, long, value_name = "INPUT", value_parser = verify_file, group = "CsvOpts")]
     pub input: String,
 
-    /// Output file
+    /// Output file, '-' for stdout, output.<format> by default
     #[arg(short, long, value_name = "OUTPUT")]
     pub output: Option<String>,
 
-    /// Output format
+    /// Sheet of a spreadsheet input, by name or 0-based index, the first one by default
+    #[arg(long, value_name = "NAME|INDEX")]
+    pub sheet: Option<String>,
+
+    /// Write to stdout instead of the default output file
+    #[arg(long, conflicts_with = "output")]
+    pub stdout: bool,
+
+    /// Output format: json, yaml, toml, ndjson, csv, md, html, parquet or arrow (IPC file)
     #[arg(long, value_parser = parse_format, default_value = "json")]
     pub format: OutputFormat,
 
-    /// Use header row
-    #[arg(long, default_value_t = true)]
-    pub header: bool,
+    /// Delimiter character of the csv output format
+    #[arg(long, value_parser = parse_csv_char, default_value = ",")]
+    pub output_delimiter: char,
+
+    /// Infer integers, floats, booleans and nulls (empty cells), one type per column from its first 1000 rows
+    #[arg(long)]
+    pub infer: bool,
+
+    /// Explicit column types overriding inference, e.g. "Kit Number:int,Active:bool"
+    #[arg(long, value_name = "COL:TYPE,...", value_parser = parse_column_type, value_delimiter = ',')]
+    pub schema: Vec<(String, CellType)>,
+
+    /// Decode rows into typed records declared in a YAML or JSON schema file
+    #[arg(long, value_name = "FILE", value_parser = verify_file, conflicts_with_all = ["infer", "schema"])]
+    pub schema_file: Option<String>,
+
+    /// Nest dotted headers such as address.city into objects, and tags.0, tags.1 into arrays
+    #[arg(long)]
+    pub unflatten: bool,
+
+    /// Only output these columns, in this order, e.g. "Name,DOB"
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+
+    /// Only output rows matching the expression, e.g. 'Position == "Goalkeeper" and `Kit Number` < 10'
+    #[arg(long = "where", value_name = "EXPR")]
+    pub filter: Option<String>,
+
+    /// Sort rows by this column: numbers numerically before text, empty cells last in either order
+    #[arg(long, value_name = "COLUMN")]
+    pub sort_by: Option<String>,
+
+    /// Sort in descending order
+    #[arg(long, requires = "sort_by")]
+    pub desc: bool,
+
+    /// Output at most N rows
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Convert chunks of the input on N threads, 0 for one per CPU
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    pub jobs: usize,
+
+    #[command(flatten)]
+    pub read: CsvReadOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvShowOpts {
+    /// Input file, '-' for stdin
+    #[arg(short, long, value_name = "INPUT", value_parser = verify_file)]
+    pub input: String,
+
+    /// Truncate cells wider than this many columns, 0 to disable
+    #[arg(long, default_value_t = 40)]
+    pub max_width: usize,
+
+    /// Show row numbers
+    #[arg(short = 'n', long)]
+    pub row_numbers: bool,
+
+    /// Rows per page, pausing between pages in a terminal, 0 to show everything at once
+    #[arg(long, default_value_t = 0)]
+    pub page_size: usize,
+
+    #[command(flatten)]
+    pub read: CsvReadOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvStatsOpts {
+    /// Input file, '-' for stdin
+    #[arg(short, long, value_name = "INPUT", value_parser = verify_file)]
+    pub input: String,
+
+    /// Number of most frequent values to report per column
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+
+    /// Output format, a table when not given
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub read: CsvReadOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvValidateOpts {
+    /// Input file, '-' for stdin
+    #[arg(short, long, value_name = "INPUT", value_parser = verify_file)]
+    pub input: String,
+
+    /// Column spec, YAML or JSON
+    #[arg(long, value_name = "SPEC", value_parser = verify_file)]
+    pub spec: String,
+
+    /// Report at most N violations, 0 to report all of them
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    pub max_errors: usize,
+
+    #[command(flatten)]
+    pub read: CsvReadOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvDiffOpts {
+    /// Old file
+    #[arg(short = 'a', long, value_name = "OLD", value_parser = verify_file)]
+    pub old: String,
+
+    /// New file
+    #[arg(short = 'b', long, value_name = "NEW", value_parser = verify_file)]
+    pub new: String,
+
+    /// Key columns identifying a row, e.g. --key Name or --key Name,DOB
+    #[arg(short, long, value_name = "COLUMN", value_delimiter = ',', required = true)]
+    pub key: Vec<String>,
+
+    /// Output format, a table when not given
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub read: CsvReadOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvJoinOpts {
+    /// Left file
+    #[arg(short, long, value_name = "LEFT", value_parser = verify_file)]
+    pub left: String,
+
+    /// Right file, loaded into memory
+    #[arg(short, long, value_name = "RIGHT", value_parser = verify_file)]
+    pub right: String,
+
+    /// Key columns present in both files, e.g. --on Name or --on Name,DOB
+    #[arg(long, value_name = "COLUMN", value_delimiter = ',', required = true)]
+    pub on: Vec<String>,
+
+    /// Join kind: inner, left or outer
+    #[arg(long, value_parser = parse_join_kind, default_value = "inner")]
+    pub how: JoinKind,
+
+    /// Output file, '-' for stdout
+    #[arg(short, long, value_name = "OUTPUT", default_value = "-")]
+    pub output: String,
+
+    /// Output format: json, yaml, toml, ndjson, csv, md, html, parquet or arrow (IPC file)
+    #[arg(long, value_parser = parse_format, default_value = "csv")]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub read: CsvReadOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct CsvCatOpts {
+    /// Input files, '-' for stdin
+    #[arg(short, long, value_name = "INPUT", value_parser = verify_file, num_args = 1.., required = true)]
+    pub input: Vec<String>,
+
+    /// Add a column with the file each row came from, named 'source' unless given
+    #[arg(long, value_name = "COLUMN", num_args = 0..=1, default_missing_value = "source")]
+    pub source: Option<String>,
+
+    /// Output file, '-' for stdout
+    #[arg(short, long, value_name = "OUTPUT", default_value = "-")]
+    pub output: String,
+
+    /// Output format: json, yaml, toml, ndjson, csv, md, html, parquet or arrow (IPC file)
+    #[arg(long, value_parser = parse_format, default_value = "csv")]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub read: CsvReadOpts,
+}
+
+#[derive(Debug, Parser)]
+pub struct ToCsvOpts {
+    /// Input file with an array of objects, '-' for stdin
+    #[arg(short, long, value_name = "INPUT", value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, '-' for stdout
+    #[arg(short, long, value_name = "OUTPUT", default_value = "output.csv")]
+    pub output: String,
+
+    /// Input format: json, yaml, toml or ndjson, guessed from the input extension by default
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
 
     /// Delimiter character
-    #[arg(short, long, default_value_t = ',')]
+    #[arg(short, long, value_parser = parse_csv_char, default_value = ",")]
     pub delimiter: char,
 }
 
-fn parse_format(format: &str) -> Result<OutputFormat, anyhow::Error> {
+/// How the input CSV is parsed
+#[derive(Debug, Clone, Args)]
+pub struct CsvReadOpts {
+    /// Treat the first row as data and name columns col1..colN
+    #[arg(long = "no-header", action = ArgAction::SetFalse)]
+    pub header: bool,
+
+    /// Delimiter character, use '\t' or 'tab' for TSV
+    #[arg(short, long, value_parser = parse_csv_char, default_value = ",")]
+    pub delimiter: char,
+
+    /// Quote character
+    #[arg(long, value_parser = parse_csv_char, default_value = "\"")]
+    pub quote: char,
+
+    /// Escape character for quotes, e.g. a backslash (quotes are doubled by default)
+    #[arg(long, value_parser = parse_csv_char)]
+    pub escape: Option<char>,
+
+    /// Skip lines starting with this character
+    #[arg(long, value_parser = parse_csv_char)]
+    pub comment: Option<char>,
+
+    /// Allow rows with a different number of fields than the header
+    #[arg(long)]
+    pub flexible: bool,
+
+    /// Input encoding, e.g. utf-8, gbk, shift_jis or utf-16le. auto reads a BOM and falls back to UTF-8
+    #[arg(long, value_parser = parse_encoding, default_value = "auto")]
+    pub encoding: InputEncoding,
+}
+
+pub(super) fn parse_format(format: &str) -> Result<OutputFormat, anyhow::Error> {
     format.parse()
 }
 
+fn parse_encoding(encoding: &str) -> Result<InputEncoding, anyhow::Error> {
+    encoding.parse()
+}
+
+fn parse_join_kind(kind: &str) -> Result<JoinKind, anyhow::Error> {
+    kind.parse()
+}
+
+fn parse_column_type(s: &str) -> Result<(String, CellType), anyhow::Error> {
+    // Split on the last ':' so column names may contain one
+    match s.rsplit_once(':') {
+        Some((name, ty)) if !name.is_empty() => Ok((name.to_string(), ty.trim().parse()?)),
+        _ => Err(anyhow::anyhow!("Invalid column type '{}', expected COL:TYPE", s)),
+    }
+}
+
+fn parse_csv_char(s: &str) -> Result<char, String> {
+    let c = match s {
+        "\\t" | "tab" => '\t',
+        _ => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(format!("'{}' is not a single character", s)),
+            }
+        }
+    };
+    // csv::ReaderBuilder works on bytes, so only ASCII is accepted
+    if c.is_ascii() {
+        Ok(c)
+    } else {
+        Err(format!("'{}' is not an ASCII character", s))
+    }
+}
+
 impl From<OutputFormat> for &str {
     fn from(format: OutputFormat) -> Self {
         match format {
             OutputFormat::Json => "json",
             OutputFormat::Yaml => "yaml",
-            // OutputFormat::Toml => "toml",
+            OutputFormat::Toml => "toml",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Md => "md",
+            OutputFormat::Html => "html",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
         }
     }
 
@@ -57,7 +393,13 @@ impl FromStr for OutputFormat {
         match s {
             "json" => Ok(OutputFormat::Json),
             "yaml" => Ok(OutputFormat::Yaml),
-            // "toml" => Ok(OutputFormat::Toml),
+            "toml" => Ok(OutputFormat::Toml),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "csv" => Ok(OutputFormat::Csv),
+            "md" => Ok(OutputFormat::Md),
+            "html" => Ok(OutputFormat::Html),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" | "ipc" => Ok(OutputFormat::Arrow),
             _ => Err(anyhow::anyhow!("Unsupported output format: '{}'", s)),
         }
     }
@@ -69,3 +411,177 @@ impl fmt::Display for OutputFormat {
     }
 
 }
+
+impl From<JoinKind> for &str {
+    fn from(kind: JoinKind) -> Self {
+        match kind {
+            JoinKind::Inner => "inner",
+            JoinKind::Left => "left",
+            JoinKind::Outer => "outer",
+        }
+    }
+}
+
+impl FromStr for JoinKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inner" => Ok(JoinKind::Inner),
+            "left" => Ok(JoinKind::Left),
+            "outer" | "full" => Ok(JoinKind::Outer),
+            _ => Err(anyhow::anyhow!("Unsupported join kind: '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for JoinKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl FromStr for InputEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Any WHATWG label works, e.g. gb2312, sjis, utf-16 or latin1
+        match s {
+            "auto" => Ok(InputEncoding::Auto),
+            _ => Encoding::for_label(s.as_bytes())
+                .map(InputEncoding::Label)
+                .ok_or_else(|| anyhow::anyhow!("Unsupported encoding: '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for InputEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputEncoding::Auto => write!(f, "auto"),
+            InputEncoding::Label(encoding) => write!(f, "{}", encoding.name()),
+        }
+    }
+}
+
+impl From<CellType> for &str {
+    fn from(ty: CellType) -> Self {
+        match ty {
+            CellType::String => "string",
+            CellType::Int => "int",
+            CellType::Float => "float",
+            CellType::Bool => "bool",
+        }
+    }
+}
+
+impl FromStr for CellType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" | "str" => Ok(CellType::String),
+            "int" | "integer" => Ok(CellType::Int),
+            "float" | "number" => Ok(CellType::Float),
+            "bool" | "boolean" => Ok(CellType::Bool),
+            _ => Err(anyhow::anyhow!("Unsupported cell type: '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for CellType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv_char() {
+        assert_eq!(parse_csv_char(";"), Ok(';'));
+        assert_eq!(parse_csv_char("\\t"), Ok('\t'));
+        assert_eq!(parse_csv_char("tab"), Ok('\t'));
+        assert_eq!(parse_csv_char("\t"), Ok('\t'));
+        assert!(parse_csv_char(";;").is_err());
+        assert!(parse_csv_char("").is_err());
+        assert!(parse_csv_char("，").is_err());
+    }
+
+    #[test]
+    fn test_output_format_round_trip() {
+        for name in ["json", "yaml", "toml", "ndjson", "csv", "md", "html", "parquet", "arrow"] {
+            let format: OutputFormat = name.parse().unwrap();
+            assert_eq!(format.to_string(), name);
+        }
+        assert_eq!("ipc".parse::<OutputFormat>().unwrap(), OutputFormat::Arrow);
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_parse_column_type() {
+        assert_eq!(parse_column_type("Kit Number:int").unwrap(), ("Kit Number".into(), CellType::Int));
+        assert_eq!(parse_column_type("a:b:bool").unwrap(), ("a:b".into(), CellType::Bool));
+        assert!(parse_column_type("Kit Number").is_err());
+        assert!(parse_column_type(":int").is_err());
+        assert!(parse_column_type("Kit Number:u128").is_err());
+
+        let opts = CsvOpts::parse_from(["csv", "-i", "-", "--schema", "Kit Number:int,DOB:string"]);
+        assert_eq!(opts.schema, vec![("Kit Number".into(), CellType::Int), ("DOB".into(), CellType::String)]);
+    }
+
+    #[test]
+    fn test_csv_command() {
+        let cmd = CsvCommand::parse_from(["csv", "-i", "-", "--format", "yaml"]);
+        assert!(cmd.cmd.is_none());
+        assert_eq!(cmd.convert.unwrap().format, OutputFormat::Yaml);
+
+        let cmd = CsvCommand::parse_from(["csv", "show", "-i", "-", "-n", "--max-width", "10"]);
+        assert!(cmd.convert.is_none());
+        assert!(matches!(cmd.cmd, Some(CsvSubCommand::Show(CsvShowOpts { row_numbers: true, max_width: 10, .. }))));
+
+        let cmd = CsvCommand::parse_from(["csv", "validate", "-i", "-", "--spec", "-"]);
+        assert!(matches!(cmd.cmd, Some(CsvSubCommand::Validate(CsvValidateOpts { max_errors: 100, .. }))));
+        assert!(CsvCommand::try_parse_from(["csv", "validate", "-i", "-"]).is_err());
+
+        let cmd = CsvCommand::parse_from(["csv", "diff", "-a", "-", "-b", "-", "--key", "Name,DOB"]);
+        assert!(matches!(cmd.cmd, Some(CsvSubCommand::Diff(CsvDiffOpts { ref key, .. })) if key == &["Name", "DOB"]));
+        assert!(CsvCommand::try_parse_from(["csv", "diff", "-a", "-", "-b", "-"]).is_err());
+
+        let cmd = CsvCommand::parse_from(["csv", "join", "-l", "-", "-r", "-", "--on", "Name", "--how", "left"]);
+        assert!(matches!(cmd.cmd, Some(CsvSubCommand::Join(CsvJoinOpts { how: JoinKind::Left, format: OutputFormat::Csv, .. }))));
+        assert!(CsvCommand::try_parse_from(["csv", "join", "-l", "-", "-r", "-", "--on", "Name", "--how", "cross"]).is_err());
+
+        let cmd = CsvCommand::parse_from(["csv", "cat", "-i", "-", "-", "--source"]);
+        assert!(matches!(cmd.cmd, Some(CsvSubCommand::Cat(CsvCatOpts { ref input, source: Some(ref source), .. })) if input.len() == 2 && source == "source"));
+        let cmd = CsvCommand::parse_from(["csv", "cat", "-i", "-", "--source", "file"]);
+        assert!(matches!(cmd.cmd, Some(CsvSubCommand::Cat(CsvCatOpts { source: Some(ref source), .. })) if source == "file"));
+
+        assert!(CsvCommand::try_parse_from(["csv"]).is_err());
+        assert!(CsvCommand::try_parse_from(["csv", "-i", "-", "--stdout", "-o", "out.json"]).is_err());
+    }
+
+    #[test]
+    fn test_csv_opts_defaults() {
+        let opts = CsvOpts::parse_from(["csv", "-i", "-"]);
+        assert!(opts.read.header);
+        assert_eq!(opts.read.delimiter, ',');
+        assert_eq!(opts.read.quote, '"');
+
+        let opts = CsvOpts::parse_from(["csv", "-i", "-", "--no-header", "-d", "tab", "--flexible"]);
+        assert!(!opts.read.header);
+        assert_eq!(opts.read.delimiter, '\t');
+        assert!(opts.read.flexible);
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("auto").unwrap(), InputEncoding::Auto);
+        assert_eq!(parse_encoding("gbk").unwrap(), InputEncoding::Label(encoding_rs::GBK));
+        assert_eq!(parse_encoding("Shift_JIS").unwrap().to_string(), "Shift_JIS");
+        assert_eq!(parse_encoding("utf-16le").unwrap(), InputEncoding::Label(encoding_rs::UTF_16LE));
+        assert!(parse_encoding("ebcdic").is_err());
+    }
+}
@@ -1,22 +1,224 @@
-use clap::Parser;
+use clap::{ArgAction, Parser, Subcommand};
 
+use crate::process::MAX_LENGTH;
+
+use super::{csv::parse_format, verify_file, OutputFormat};
+
+/// `rcli genpass` generates passwords, `rcli genpass check` audits existing ones
+#[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct GenpassCommand {
+    #[command(subcommand)]
+    pub cmd: Option<GenpassSubCommand>,
+
+    #[command(flatten)]
+    pub generate: GenpassOpts,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum GenpassSubCommand {
+    #[command(about = "Check the strength of existing passwords")]
+    Check(GenpassCheckOpts),
+}
 
 #[derive(Debug, Parser)]
 pub struct GenpassOpts {
-    /// Length of the password
-    #[arg(short, long, default_value_t = 16)]
-    pub length: u8,
+    /// Length of the password, from 1 to 4096
+    #[arg(short, long, value_parser = clap::value_parser!(u16).range(1..=MAX_LENGTH as i64), default_value_t = 16)]
+    pub length: u16,
+
+    /// Number of passwords to generate
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    pub count: usize,
+
+    /// Regenerate until zxcvbn scores the password at least N, from 0 to 4
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=4), default_value_t = 0)]
+    pub min_score: u8,
 
-    /// Use uppercase letters
-    #[arg(long, default_value_t = true)]
+    /// Print each password with its zxcvbn strength in this format, e.g. json or csv, instead of plain lines
+    #[arg(long, value_name = "FORMAT", value_parser = parse_format)]
+    pub output: Option<OutputFormat>,
+
+    /// Leave out uppercase letters
+    #[arg(long = "no-uppercase", action = ArgAction::SetFalse)]
     pub uppercase: bool,
 
-    #[arg(long, default_value_t = true)]
+    /// Leave out lowercase letters
+    #[arg(long = "no-lowercase", action = ArgAction::SetFalse)]
     pub lowercase: bool,
 
-    #[arg(long, default_value_t = true)]
+    /// Leave out digits
+    #[arg(long = "no-number", action = ArgAction::SetFalse)]
     pub number: bool,
 
-    #[arg(long, default_value_t = true)]
+    /// Leave out symbols
+    #[arg(long = "no-symbol", action = ArgAction::SetFalse)]
     pub symbol: bool,
+
+    /// Characters never to use, e.g. "0O1lI"
+    #[arg(long, value_name = "CHARS", default_value = "")]
+    pub exclude: String,
+
+    /// Symbols to use instead of !@#$%^&*-_
+    #[arg(long, value_name = "SET")]
+    pub symbols: Option<String>,
+
+    /// Draw every character from this alphabet instead of the character classes
+    #[arg(long, value_name = "CHARS", conflicts_with = "symbols")]
+    pub charset: Option<String>,
+
+    /// At least N uppercase letters, always one when they are enabled
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub min_upper: usize,
+
+    /// At least N digits, always one when they are enabled
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub min_digits: usize,
+
+    /// At least N symbols, always one when they are enabled
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub min_symbols: usize,
+
+    /// Never put the same character twice in a row, as in "aa"
+    #[arg(long)]
+    pub no_repeat: bool,
+
+    /// Never put neighbouring letters or digits next to each other, as in "ab", "ED" or "12"
+    #[arg(long)]
+    pub no_sequential: bool,
+
+    /// Generate a passphrase of N words instead of random characters
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["length", "exclude", "symbols", "charset", "min_upper", "min_digits", "min_symbols", "no_repeat", "no_sequential"]
+    )]
+    pub words: Option<usize>,
+
+    /// Fill a template instead, C and c for a consonant, V and v for a vowel, 9 for a digit and ! for a symbol,
+    /// e.g. "Cvccvc-99-Cvccvc". Other characters are kept, a backslash keeps the next one too
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all = [
+            "length", "words", "uppercase", "lowercase", "number", "symbol", "exclude", "charset",
+            "min_upper", "min_digits", "min_symbols", "no_repeat", "no_sequential"
+        ]
+    )]
+    pub pattern: Option<String>,
+
+    /// Alternate consonants and vowels, easy to read out over the phone
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "pattern", "words", "uppercase", "lowercase", "number", "symbol", "exclude", "symbols", "charset",
+            "min_upper", "min_digits", "min_symbols", "no_repeat", "no_sequential"
+        ]
+    )]
+    pub pronounceable: bool,
+
+    /// Word list file, one word per line, optionally after dice numbers as in the EFF lists
+    #[arg(long, value_name = "FILE", value_parser = verify_file, requires = "words")]
+    pub wordlist: Option<String>,
+
+    /// Separator between the words of a passphrase
+    #[arg(long, default_value = "-", requires = "words")]
+    pub separator: String,
+
+    /// Capitalize every word of a passphrase
+    #[arg(long, requires = "words")]
+    pub capitalize: bool,
+
+    /// Append N random digits to random words of a passphrase
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "words")]
+    pub digits: usize,
+}
+
+#[derive(Debug, Parser)]
+pub struct GenpassCheckOpts {
+    /// File of passwords, one per line, '-' for stdin
+    #[arg(short, long, value_name = "INPUT", value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Words an attacker would try first, e.g. the user and company name
+    #[arg(long, value_name = "WORD,...", value_delimiter = ',')]
+    pub context: Vec<String>,
+
+    /// Exit with an error when a password scores below N, from 0 to 4
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=4), default_value_t = 0)]
+    pub min_score: u8,
+
+    /// Output format, a table when not given
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<OutputFormat>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_genpass_command() {
+        let cmd = GenpassCommand::parse_from(["genpass", "-l", "20"]);
+        assert!(cmd.cmd.is_none());
+        assert_eq!(cmd.generate.length, 20);
+
+        let cmd = GenpassCommand::parse_from(["genpass", "check", "--context", "alice,acme", "--min-score", "3"]);
+        let Some(GenpassSubCommand::Check(opts)) = cmd.cmd else { panic!("expected check") };
+        assert_eq!((opts.input.as_str(), opts.context, opts.min_score), ("-", vec!["alice".to_string(), "acme".to_string()], 3));
+        assert!(GenpassCommand::try_parse_from(["genpass", "check", "--min-score", "5"]).is_err());
+        assert!(GenpassCommand::try_parse_from(["genpass", "-l", "20", "check"]).is_err());
+
+        assert_eq!(GenpassCommand::parse_from(["genpass", "-l", "4096"]).generate.length, 4096);
+        assert!(GenpassCommand::try_parse_from(["genpass", "-l", "4097"]).is_err());
+        assert!(GenpassCommand::try_parse_from(["genpass", "-l", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_genpass_policy() {
+        let opts = GenpassOpts::parse_from(["genpass", "--exclude", "0O", "--symbols", "#!", "--min-digits", "3", "--no-repeat"]);
+        assert_eq!(opts.exclude, "0O");
+        assert_eq!(opts.symbols.as_deref(), Some("#!"));
+        assert_eq!((opts.min_upper, opts.min_digits, opts.min_symbols), (0, 3, 0));
+        assert!(opts.no_repeat && !opts.no_sequential);
+        assert!(GenpassOpts::try_parse_from(["genpass", "--charset", "abc", "--symbols", "#"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_genpass_classes() {
+        let opts = GenpassOpts::parse_from(["genpass"]);
+        assert!(opts.uppercase && opts.lowercase && opts.number && opts.symbol);
+        let opts = GenpassOpts::parse_from(["genpass", "--no-uppercase", "--no-lowercase", "--no-symbol", "-l", "6"]);
+        assert!(!opts.uppercase && !opts.lowercase && opts.number && !opts.symbol);
+        assert!(GenpassOpts::try_parse_from(["genpass", "--uppercase", "false"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_genpass_batch() {
+        let opts = GenpassOpts::parse_from(["genpass", "-c", "10", "--output", "csv"]);
+        assert_eq!((opts.count, opts.output), (10, Some(OutputFormat::Csv)));
+        assert_eq!(GenpassOpts::parse_from(["genpass"]).output, None);
+        assert!(GenpassOpts::try_parse_from(["genpass", "--output", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_genpass_words() {
+        let opts = GenpassOpts::parse_from(["genpass", "--words", "5", "--separator", " ", "--capitalize", "--digits", "2"]);
+        assert_eq!(opts.words, Some(5));
+        assert_eq!((opts.separator.as_str(), opts.capitalize, opts.digits), (" ", true, 2));
+        assert!(GenpassOpts::try_parse_from(["genpass", "--words", "5", "-l", "20"]).is_err());
+        assert!(GenpassOpts::try_parse_from(["genpass", "--capitalize"]).is_err());
+        assert!(GenpassOpts::try_parse_from(["genpass", "--words", "5", "--wordlist", "missing.txt"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_genpass_pattern() {
+        let opts = GenpassOpts::parse_from(["genpass", "--pattern", "Cvccvc-99", "--symbols", "#"]);
+        assert_eq!(opts.pattern.as_deref(), Some("Cvccvc-99"));
+        let opts = GenpassOpts::parse_from(["genpass", "--pronounceable", "-l", "10"]);
+        assert!(opts.pronounceable && opts.length == 10);
+        assert!(GenpassOpts::try_parse_from(["genpass", "--pattern", "99", "-l", "10"]).is_err());
+        assert!(GenpassOpts::try_parse_from(["genpass", "--pattern", "99", "--words", "4"]).is_err());
+        assert!(GenpassOpts::try_parse_from(["genpass", "--pronounceable", "--no-number"]).is_err());
+    }
 }
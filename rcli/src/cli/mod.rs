@@ -7,9 +7,9 @@ mod http;
 use std::path::{Path, PathBuf};
 
 use clap::Parser;
-use self::{csv::CsvOpts, genpass::GenpassOpts};
 
-pub use self::csv::OutputFormat;
+pub use self::csv::{CellType, CsvCatOpts, CsvCommand, CsvDiffOpts, CsvJoinOpts, CsvOpts, CsvReadOpts, CsvShowOpts, CsvStatsOpts, CsvSubCommand, CsvValidateOpts, InputEncoding, JoinKind, OutputFormat, ToCsvOpts};
+pub use self::genpass::{GenpassCheckOpts, GenpassCommand, GenpassOpts, GenpassSubCommand};
 pub use self::base64::{Base64SubCommand, Base64Format};
 pub use self::text::{TextSubCommand, TextSignFormat};
 pub use self::http::{HttpSubCommand};
@@ -26,10 +26,13 @@ pub struct Opts {
 pub enum SubCommand {
 
     #[command(name = "csv", about = "Show CSV, or convert CSV to other formats")]
-    Csv(CsvOpts),
+    Csv(Box<CsvCommand>),
 
-    #[command(name = "genpass", about = "Generate random passwords")]
-    GenPass(GenpassOpts),
+    #[command(name = "tocsv", about = "Convert JSON, YAML or TOML arrays of objects to CSV")]
+    ToCsv(ToCsvOpts),
+
+    #[command(name = "genpass", about = "Generate random passwords, or check existing ones")]
+    GenPass(GenpassCommand),
 
     #[command(subcommand)]
     Base64(Base64SubCommand),
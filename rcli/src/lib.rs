@@ -2,8 +2,8 @@ mod cli;
 mod process;
 mod utils;
 
-pub use cli::{Opts, SubCommand, OutputFormat, Base64SubCommand,
+pub use cli::{Opts, SubCommand, CellType, CsvCatOpts, CsvCommand, CsvDiffOpts, CsvJoinOpts, CsvOpts, CsvReadOpts, CsvShowOpts, CsvStatsOpts, CsvSubCommand, CsvValidateOpts, GenpassCheckOpts, GenpassCommand, GenpassOpts, GenpassSubCommand, InputEncoding, JoinKind, OutputFormat, ToCsvOpts, Base64SubCommand,
     Base64Format, TextSubCommand, TextSignFormat, HttpSubCommand};
-pub use process::{process_csv, process_genpass, process_encode, process_decode,
+pub use process::{FieldSpec, FieldType, RecordDecoder, RecordSchema, process_csv, convert_csv, convert_csv_parallel, CHUNK_SIZE, process_csv_cat, process_csv_diff, process_csv_join, process_csv_show, process_csv_stats, process_csv_validate, process_to_csv, process_genpass, process_genpass_batch, GeneratedPassword, Generator, PasswordPolicy, MAX_LENGTH, estimate_strength, Strength, check_passwords, process_genpass_check, CheckedPassword, parse_wordlist, process_passphrase, Passphrase, PassphrasePolicy, process_pattern, PatternPolicy, process_encode, process_decode,
     process_sign, process_verify, process_generate, process_http_serve};
 pub use utils::*;
@@ -1,23 +1,6 @@
 use clap::Parser;
-// rcli csv -i input.csv -o output.json --header -d ','
-use rcli::{process_csv, process_decode, process_encode, process_generate, process_genpass, process_http_serve, process_sign, process_verify, Base64SubCommand, HttpSubCommand, Opts, SubCommand, TextSignFormat, TextSubCommand};
-use serde::{Serialize, Deserialize};
-use zxcvbn::zxcvbn;
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct Player {
-    // #[serde(rename = "Name")]
-    name: String,
-    // #[serde(rename = "Position")]
-    position: String,
-    #[serde(rename = "DOB")]
-    dob: String,
-    // #[serde(rename = "Nationality")]
-    nationality: String,
-    #[serde(rename = "Kit Number")]
-    kit: u8
-}
+// rcli csv -i input.csv -o output.json --no-header -d ','
+use rcli::{process_csv, process_csv_cat, process_csv_diff, process_csv_join, process_csv_show, process_csv_stats, process_csv_validate, process_to_csv, CsvSubCommand, process_decode, process_encode, process_generate, process_genpass_batch, process_genpass_check, GenpassSubCommand, process_http_serve, process_sign, process_verify, Base64SubCommand, HttpSubCommand, Opts, SubCommand, TextSignFormat, TextSubCommand};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -25,25 +8,20 @@ async fn main() -> anyhow::Result<()> {
     // println!("Hello, world!");
     let opts = Opts::parse();
     match opts.cmd {
-        SubCommand::Csv(opts) => {
-            let output = if let Some(output) = opts.output {
-                output.clone()
-            } else {
-                // Default output file name
-                format!("output.{}", opts.format)
-            };
-            process_csv(&opts.input, output, opts.format)?
+        SubCommand::Csv(cmd) => match (cmd.cmd, cmd.convert) {
+            (Some(CsvSubCommand::Show(opts)), _) => process_csv_show(&opts)?,
+            (Some(CsvSubCommand::Stats(opts)), _) => process_csv_stats(&opts)?,
+            (Some(CsvSubCommand::Validate(opts)), _) => process_csv_validate(&opts)?,
+            (Some(CsvSubCommand::Diff(opts)), _) => process_csv_diff(&opts)?,
+            (Some(CsvSubCommand::Join(opts)), _) => process_csv_join(&opts)?,
+            (Some(CsvSubCommand::Cat(opts)), _) => process_csv_cat(&opts)?,
+            (None, Some(opts)) => process_csv(&opts)?,
+            (None, None) => anyhow::bail!("Missing --input or a csv subcommand"),
         },
-        SubCommand::GenPass(opts) => {
-            // Here you would call the function to generate passwords
-            // For now, we just print the options
-            // println!("Generating passwords options: {:?}", opts);
-
-            let password = process_genpass(opts.length, opts.uppercase, opts.lowercase, opts.number, opts.symbol)?;
-            println!("{}", password);
-
-            let estimate = zxcvbn(&password, &[]);
-            eprintln!("Password strength: {:?}", estimate.score());
+        SubCommand::ToCsv(opts) => process_to_csv(&opts)?,
+        SubCommand::GenPass(cmd) => match cmd.cmd {
+            Some(GenpassSubCommand::Check(opts)) => process_genpass_check(&opts)?,
+            None => process_genpass_batch(&cmd.generate, std::io::stdout().lock())?,
         },
         SubCommand::Base64(subcmd) => match subcmd {
             Base64SubCommand::Encode(opts) => {
@@ -61,9 +39,6 @@ async fn main() -> anyhow::Result<()> {
             },
         },
         SubCommand::Text(subcmd) => {
-            // Here you would call the function to handle text signing or verification
-            // For now, we just print the options
-            println!("Text subcommand options: {:?}", subcmd);
             match subcmd {
                 TextSubCommand::Sign(opts) => {
                     process_sign(&opts.input, &opts.key, opts.format)?;
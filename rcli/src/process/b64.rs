@@ -27,8 +27,8 @@ pub fn process_decode(input: &str, format: Base64Format) -> anyhow::Result<Vec<u
     let buffer = buffer.trim();
 
     let decoded = match format {
-        Base64Format::Standard => BASE64_STANDARD.decode(&buffer)?,
-        Base64Format::UrlSafe => BASE64_URL_SAFE.decode(&buffer)?,
+        Base64Format::Standard => BASE64_STANDARD.decode(buffer)?,
+        Base64Format::UrlSafe => BASE64_URL_SAFE.decode(buffer)?,
     };
     Ok(decoded)
 }
@@ -41,7 +41,8 @@ mod tests {
     fn test_process_encode() -> anyhow::Result<()> {
         let input = "fixtures/tmp.b64.revert";
         let encoded = process_encode(input, Base64Format::Standard)?;
-        assert_eq!(encoded, "SGVsbG8gV29ybGQh");
+        // The fixture ends with a newline, which is encoded too
+        assert_eq!(encoded, "SGVsbG8gV29ybGQhCg==");
         Ok(())
     }
 
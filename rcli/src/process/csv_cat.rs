@@ -0,0 +1,107 @@
+use std::io::Read;
+
+use csv::StringRecord;
+use serde_json::{Map, Value};
+
+use crate::{cli::{CsvCatOpts, CsvReadOpts}, get_reader, get_writer};
+
+use super::csv_convert::{csv_headers, csv_reader, end_output, row_writer, RowWriter};
+
+/// Concatenate the inputs, each given as (source name, reader). The output header is the union
+/// of the input headers in order of first appearance, columns missing from a file are null.
+/// With `source`, a column of that name holding the source name comes first
+pub fn cat_csv<R: Read>(
+    inputs: Vec<(String, R)>,
+    opts: &CsvReadOpts,
+    source: Option<&str>,
+    writer: &mut dyn RowWriter,
+) -> anyhow::Result<()> {
+    if !opts.header {
+        anyhow::bail!("csv cat needs a header row in every file to reconcile the columns");
+    }
+    // Every header is needed before the first row is written, so all inputs are opened up front
+    let mut readers = Vec::with_capacity(inputs.len());
+    let mut columns = source.map(String::from).into_iter().collect::<Vec<_>>();
+    for (name, reader) in inputs {
+        let mut reader = csv_reader(opts, reader);
+        let headers = csv_headers(opts, &mut reader)?;
+        if let Some(source) = source.filter(|source| headers.iter().any(|header| header == source)) {
+            anyhow::bail!("Source column '{}' is already a column of {}", source, name);
+        }
+        for header in &headers {
+            if !columns.contains(header) {
+                columns.push(header.clone());
+            }
+        }
+        readers.push((name, headers, reader));
+    }
+    writer.set_columns(columns.clone());
+
+    let mut record = StringRecord::new();
+    for (name, headers, mut reader) in readers {
+        while reader.read_record(&mut record)? {
+            let mut map = columns.iter().map(|column| (column.clone(), Value::Null)).collect::<Map<_, _>>();
+            if let Some(source) = source {
+                map.insert(source.into(), Value::String(name.clone()));
+            }
+            for (header, cell) in headers.iter().zip(record.iter()) {
+                map.insert(header.clone(), Value::String(cell.into()));
+            }
+            writer.write_row(&Value::Object(map))?;
+        }
+    }
+    writer.finish()
+}
+
+pub fn process_csv_cat(opts: &CsvCatOpts) -> anyhow::Result<()> {
+    if opts.input.iter().filter(|input| *input == "-").count() > 1 {
+        anyhow::bail!("Stdin can only be read once");
+    }
+    let inputs = opts
+        .input
+        .iter()
+        .map(|input| Ok((input.clone(), get_reader(input)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut output = get_writer(&opts.output)?;
+    {
+        let mut writer = row_writer(opts.format, ',', &mut output);
+        cat_csv(inputs, &opts.read, opts.source.as_deref(), writer.as_mut())?;
+    }
+    end_output(&mut output, opts.format, &opts.output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn cat(inputs: &[(&str, &str)], args: &[&str]) -> anyhow::Result<String> {
+        let opts = CsvCatOpts::parse_from(["cat", "-i", "-"].iter().chain(args));
+        let inputs = inputs.iter().map(|(name, content)| (name.to_string(), content.as_bytes())).collect();
+        let mut output = Vec::new();
+        let mut writer = row_writer(opts.format, ',', &mut output);
+        cat_csv(inputs, &opts.read, opts.source.as_deref(), writer.as_mut())?;
+        drop(writer);
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_cat_csv() -> anyhow::Result<()> {
+        let inputs = [("2019.csv", "Name,Kit\nBuffon,1\n"), ("2020.csv", "Kit,Name,Club\n77,Buffon,Juventus\n")];
+        assert_eq!(cat(&inputs, &[])?, "Name,Kit,Club\nBuffon,1,\nBuffon,77,Juventus\n");
+        assert_eq!(
+            cat(&inputs, &["--source", "file"])?,
+            "file,Name,Kit,Club\n2019.csv,Buffon,1,\n2020.csv,Buffon,77,Juventus\n"
+        );
+        assert_eq!(cat(&inputs, &["--format", "ndjson", "--source"])?.lines().last(), Some(r#"{"source":"2020.csv","Name":"Buffon","Kit":"77","Club":"Juventus"}"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cat_csv_errors() {
+        assert!(cat(&[("a.csv", "Name,source\nBuffon,x\n")], &["--source"]).is_err());
+        assert!(cat(&[("a.csv", "Buffon,1\n")], &["--no-header"]).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, RecordBatchOptions, StringArray};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use serde_json::Value;
+
+use crate::cli::OutputFormat;
+
+use super::csv_convert::{cell_text, RowWriter};
+
+/// Rows per record batch, each batch is one Parquet row group
+const BATCH_ROWS: usize = 65536;
+
+/// Encodes batches into an in-memory buffer, which is drained to the output after every batch
+enum Encoder {
+    Parquet(ArrowWriter<Vec<u8>>),
+    Arrow(FileWriter<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(format: OutputFormat, schema: &SchemaRef) -> anyhow::Result<Self> {
+        Ok(match format {
+            OutputFormat::Parquet => Encoder::Parquet(ArrowWriter::try_new(Vec::new(), schema.clone(), None)?),
+            _ => Encoder::Arrow(FileWriter::try_new(Vec::new(), schema)?),
+        })
+    }
+
+    fn write(&mut self, schema: &SchemaRef, rows: &[Value]) -> anyhow::Result<()> {
+        let columns = schema.fields().iter().map(|field| column(rows, field)).collect::<anyhow::Result<Vec<_>>>()?;
+        let options = RecordBatchOptions::new().with_row_count(Some(rows.len()));
+        let batch = RecordBatch::try_new_with_options(schema.clone(), columns, &options)?;
+        match self {
+            Encoder::Parquet(writer) => {
+                writer.write(&batch)?;
+                // Close the row group now instead of buffering rows up to the default row group size
+                writer.flush()?;
+            },
+            Encoder::Arrow(writer) => writer.write(&batch)?,
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        match self {
+            Encoder::Parquet(writer) => {
+                writer.finish()?;
+            },
+            Encoder::Arrow(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+
+    /// Moves the encoded bytes to `writer`
+    fn drain(&mut self, writer: &mut impl Write) -> anyhow::Result<()> {
+        let buffer = match self {
+            Encoder::Parquet(writer) => writer.inner_mut(),
+            Encoder::Arrow(writer) => writer.get_mut(),
+        };
+        writer.write_all(buffer)?;
+        buffer.clear();
+        Ok(())
+    }
+}
+
+/// Numbers the spill files of one process, tests write several at once
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Rows waiting for the schema, as NDJSON in a temporary file that is removed on drop
+struct Spill {
+    path: PathBuf,
+    file: BufWriter<File>,
+}
+
+impl Spill {
+    fn new() -> anyhow::Result<Self> {
+        let name = format!("rcli-{}-{}.ndjson", std::process::id(), SPILLS.fetch_add(1, Ordering::Relaxed));
+        let path = std::env::temp_dir().join(name);
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)?;
+        Ok(Spill { path, file: BufWriter::new(file) })
+    }
+
+    fn push(&mut self, row: &Value) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.file, row)?;
+        self.file.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// The rows pushed so far, read back from the start
+    fn rows(&mut self) -> anyhow::Result<impl Iterator<Item = serde_json::Result<Value>> + '_> {
+        self.file.flush()?;
+        let file = self.file.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        Ok(serde_json::Deserializer::from_reader(BufReader::new(file)).into_iter())
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Parquet or Arrow IPC file. Both formats need the schema before the first batch, so rows are spilled
+/// to a temporary file while the column types are inferred from all of them: booleans, integers, floats,
+/// or text for anything else. The spilled rows are then encoded in batches of `BATCH_ROWS`.
+pub struct ColumnarWriter<W: Write> {
+    writer: W,
+    format: OutputFormat,
+    /// Columns in order of first appearance with the type of their values so far, none while all are null
+    columns: Vec<(String, Option<DataType>)>,
+    index: HashMap<String, usize>,
+    spill: Option<Spill>,
+}
+
+impl<W: Write> ColumnarWriter<W> {
+    pub fn new(format: OutputFormat, writer: W) -> Self {
+        ColumnarWriter { writer, format, columns: Vec::new(), index: HashMap::new(), spill: None }
+    }
+
+    /// All columns nullable, the ones without any value are text
+    fn schema(&self) -> SchemaRef {
+        let fields = self
+            .columns
+            .iter()
+            .map(|(name, ty)| Field::new(name, ty.clone().unwrap_or(DataType::Utf8), true))
+            .collect::<Vec<_>>();
+        Arc::new(Schema::new(fields))
+    }
+}
+
+impl<W: Write> RowWriter for ColumnarWriter<W> {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        for (name, value) in row.as_object().into_iter().flatten() {
+            let i = match self.index.get(name) {
+                Some(&i) => i,
+                None => {
+                    self.index.insert(name.clone(), self.columns.len());
+                    self.columns.push((name.clone(), None));
+                    self.columns.len() - 1
+                },
+            };
+            let ty = &mut self.columns[i].1;
+            *ty = merge_type(ty.take(), value);
+        }
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => self.spill.insert(Spill::new()?),
+        };
+        spill.push(row)
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let schema = self.schema();
+        let mut encoder = Encoder::new(self.format, &schema)?;
+        if let Some(spill) = &mut self.spill {
+            let mut rows = Vec::with_capacity(BATCH_ROWS);
+            for row in spill.rows()? {
+                rows.push(row?);
+                if rows.len() == BATCH_ROWS {
+                    encoder.write(&schema, &rows)?;
+                    encoder.drain(&mut self.writer)?;
+                    rows.clear();
+                }
+            }
+            if !rows.is_empty() {
+                encoder.write(&schema, &rows)?;
+            }
+        }
+        self.spill = None;
+        encoder.finish()?;
+        encoder.drain(&mut self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Widens `ty` to also hold `value`, integers widen to floats and mixed columns become text
+fn merge_type(ty: Option<DataType>, value: &Value) -> Option<DataType> {
+    let next = match value {
+        Value::Null => return ty,
+        Value::Bool(_) => DataType::Boolean,
+        Value::Number(n) if n.is_i64() => DataType::Int64,
+        Value::Number(_) => DataType::Float64,
+        _ => DataType::Utf8,
+    };
+    Some(match (ty, next) {
+        (None, next) => next,
+        (Some(ty), next) if ty == next => ty,
+        (Some(DataType::Int64 | DataType::Float64), DataType::Int64 | DataType::Float64) => DataType::Float64,
+        _ => DataType::Utf8,
+    })
+}
+
+fn column(rows: &[Value], field: &Field) -> anyhow::Result<ArrayRef> {
+    let values = || rows.iter().map(|row| row.get(field.name()).filter(|value| !value.is_null()));
+    let mismatch = |value: &Value| {
+        anyhow::anyhow!(
+            "Column '{}' was inferred as {}, but '{}' does not fit",
+            field.name(),
+            field.data_type(),
+            cell_text(value)
+        )
+    };
+    let array: ArrayRef = match field.data_type() {
+        DataType::Boolean => Arc::new(
+            values()
+                .map(|value| value.map(|value| value.as_bool().ok_or_else(|| mismatch(value))).transpose())
+                .collect::<anyhow::Result<BooleanArray>>()?,
+        ),
+        DataType::Int64 => Arc::new(
+            values()
+                .map(|value| value.map(|value| value.as_i64().ok_or_else(|| mismatch(value))).transpose())
+                .collect::<anyhow::Result<Int64Array>>()?,
+        ),
+        DataType::Float64 => Arc::new(
+            values()
+                .map(|value| value.map(|value| value.as_f64().ok_or_else(|| mismatch(value))).transpose())
+                .collect::<anyhow::Result<Float64Array>>()?,
+        ),
+        _ => Arc::new(values().map(|value| value.map(cell_text)).collect::<StringArray>()),
+    };
+    Ok(array)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use arrow_array::{cast::AsArray, types::{Float64Type, Int64Type}};
+    use arrow_ipc::reader::FileReader;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use serde_json::json;
+
+    use super::*;
+
+    fn write(format: OutputFormat, rows: &[Value]) -> anyhow::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut writer = ColumnarWriter::new(format, &mut output);
+        for row in rows {
+            writer.write_row(row)?;
+        }
+        writer.finish()?;
+        Ok(output)
+    }
+
+    fn rows() -> Vec<Value> {
+        vec![
+            json!({"Name": "Buffon", "Kit": 77, "Rating": 8, "Captain": true, "Tags": ["keeper"]}),
+            json!({"Name": "Perin", "Kit": null, "Rating": 7.5, "Captain": false}),
+        ]
+    }
+
+    fn check(batch: &RecordBatch) {
+        let schema = batch.schema();
+        let types = schema.fields().iter().map(|field| (field.name().as_str(), field.data_type().clone())).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                ("Name", DataType::Utf8),
+                ("Kit", DataType::Int64),
+                ("Rating", DataType::Float64),
+                ("Captain", DataType::Boolean),
+                ("Tags", DataType::Utf8),
+            ]
+        );
+        let names = batch.column(0).as_string::<i32>();
+        assert_eq!(names.iter().collect::<Vec<_>>(), [Some("Buffon"), Some("Perin")]);
+        let kits = batch.column(1).as_primitive::<Int64Type>();
+        assert_eq!(kits.iter().collect::<Vec<_>>(), [Some(77), None]);
+        let ratings = batch.column(2).as_primitive::<Float64Type>();
+        assert_eq!(ratings.iter().collect::<Vec<_>>(), [Some(8.0), Some(7.5)]);
+        assert_eq!(batch.column(3).as_boolean().iter().collect::<Vec<_>>(), [Some(true), Some(false)]);
+        let tags = batch.column(4).as_string::<i32>();
+        assert_eq!(tags.iter().collect::<Vec<_>>(), [Some("[\"keeper\"]"), None]);
+    }
+
+    #[test]
+    fn test_parquet_round_trip() -> anyhow::Result<()> {
+        let output = write(OutputFormat::Parquet, &rows())?;
+        assert!(output.starts_with(b"PAR1") && output.ends_with(b"PAR1"));
+        let path = std::env::temp_dir().join(format!("rcli-test-{}.parquet", std::process::id()));
+        std::fs::write(&path, output)?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path)?)?.build()?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(batches.len(), 1);
+        check(&batches[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_arrow_round_trip() -> anyhow::Result<()> {
+        let output = write(OutputFormat::Arrow, &rows())?;
+        let reader = FileReader::try_new(Cursor::new(output), None)?;
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(batches.len(), 1);
+        check(&batches[0]);
+
+        let output = write(OutputFormat::Arrow, &[])?;
+        let reader = FileReader::try_new(Cursor::new(output), None)?;
+        assert_eq!(reader.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_columnar_batches() -> anyhow::Result<()> {
+        let rows = (0..BATCH_ROWS + 1).map(|i| json!({ "n": i })).collect::<Vec<_>>();
+        let output = write(OutputFormat::Arrow, &rows)?;
+        let batches = FileReader::try_new(Cursor::new(output), None)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).collect::<Vec<_>>(), [BATCH_ROWS, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_columnar_widens_after_first_batch() -> anyhow::Result<()> {
+        // Values after the first batch widen the type of their column or add a column, even to earlier batches
+        let mut rows = (0..BATCH_ROWS).map(|i| json!({ "n": i, "x": i })).collect::<Vec<_>>();
+        rows.push(json!({ "n": 1.5, "x": "n/a", "late": true }));
+        let output = write(OutputFormat::Arrow, &rows)?;
+        let batches = FileReader::try_new(Cursor::new(output), None)?.collect::<Result<Vec<_>, _>>()?;
+        let schema = batches[0].schema();
+        let types = schema.fields().iter().map(|field| (field.name().as_str(), field.data_type().clone())).collect::<Vec<_>>();
+        assert_eq!(types, [("n", DataType::Float64), ("x", DataType::Utf8), ("late", DataType::Boolean)]);
+        let (first, last) = (&batches[0], &batches[1]);
+        assert_eq!(first.column(0).as_primitive::<Float64Type>().value(2), 2.0);
+        assert_eq!(first.column(1).as_string::<i32>().value(2), "2");
+        assert_eq!(first.column(2).null_count(), BATCH_ROWS);
+        assert_eq!(last.column(0).as_primitive::<Float64Type>().value(0), 1.5);
+        assert_eq!(last.column(1).as_string::<i32>().value(0), "n/a");
+        assert!(last.column(2).as_boolean().value(0));
+        Ok(())
+    }
+}
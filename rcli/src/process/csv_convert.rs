@@ -1,47 +1,956 @@
-use csv::Reader;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-
-use crate::cli::OutputFormat;
-
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct Player {
-    // #[serde(rename = "Name")]
-    name: String,
-    // #[serde(rename = "Position")]
-    position: String,
-    #[serde(rename = "DOB")]
-    dob: String,
-    // #[serde(rename = "Nationality")]
-    nationality: String,
-    #[serde(rename = "Kit Number")]
-    kit: u8
-}
-
-
-pub fn process_csv(input: &str, output: String, format: OutputFormat) -> anyhow::Result<()> {
-
-    let mut reader = Reader::from_path(input)?;
-    let mut ret = Vec::with_capacity(128);
-    let headers = reader.headers()?.clone();
-    for result in reader.records() {
-        // The iterator yields Result<StringRecord, Error>, so we check the
-        // error here.
-        let record = result?;
-        let json_value = headers.iter().zip(record.iter()).collect::<Value>();
-        ret.push(json_value);
-    }
-
-    // Convert the Vec<Value> to a format based on the OutputFormat
-    let content = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(&ret)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&ret)?,
-        // OutputFormat::Toml => toml::to_string(&ret)?,
-    };
+use std::{borrow::Cow, collections::{HashMap, HashSet}, io::{Read, Write}, path::Path};
+
+use csv::{Reader, ReaderBuilder, StringRecord};
+use encoding_rs::UTF_8;
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{cli::{CellType, CsvOpts, CsvReadOpts, InputEncoding, OutputFormat}, get_reader, get_writer};
+
+use super::{
+    csv_columnar::ColumnarWriter,
+    csv_parallel::{convert_csv_parallel, CHUNK_SIZE},
+    csv_query::Query,
+    csv_record::{RecordDecoder, RecordSchema},
+    csv_sheet::{convert_sheet, is_spreadsheet},
+    csv_types::{column_value, infer_column_types, parse_value},
+    flatten::unflatten_object,
+};
+
+/// Writes converted rows one at a time, so memory stays bounded by a single row
+pub trait RowWriter {
+    /// Columns known before the first row, tabular formats write them as header even when no row follows
+    fn set_columns(&mut self, _columns: Vec<String>) {}
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()>;
+    fn finish(&mut self) -> anyhow::Result<()>;
+}
+
+/// Pretty printed JSON array, identical to `serde_json::to_string_pretty` on the whole Vec
+struct JsonWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+/// YAML sequence, identical to `serde_yaml::to_string` on the whole Vec
+struct YamlWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+impl<W: Write> RowWriter for JsonWriter<W> {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        let open: &[u8] = if self.count == 0 { b"[\n" } else { b",\n" };
+        self.writer.write_all(open)?;
+        // Indent every line of the element by one level to nest it inside the array
+        let content = serde_json::to_string_pretty(row)?;
+        for (i, line) in content.lines().enumerate() {
+            if i > 0 {
+                self.writer.write_all(b"\n")?;
+            }
+            self.writer.write_all(b"  ")?;
+            self.writer.write_all(line.as_bytes())?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let close: &[u8] = if self.count == 0 { b"[]" } else { b"\n]" };
+        self.writer.write_all(close)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RowWriter for YamlWriter<W> {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        // A one element sequence renders as a single "- " item, so items concatenate cleanly
+        let content = serde_yaml::to_string(std::slice::from_ref(row))?;
+        self.writer.write_all(content.as_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if self.count == 0 {
+            self.writer.write_all(b"[]\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// One `[[rows]]` entry of an array table, TOML has no top-level arrays
+#[derive(Serialize)]
+struct TomlRow<'a> {
+    rows: [&'a Value; 1],
+}
+
+/// TOML document with every row under a top-level `[[rows]]` array table
+struct TomlWriter<W: Write> {
+    writer: W,
+    count: usize,
+}
+
+/// Newline delimited JSON, one compact object per line
+struct NdjsonWriter<W: Write> {
+    writer: W,
+}
+
+/// Header of a tabular output: the known columns, then keys of the first row that are not among them.
+/// Keys that only appear later are appended too, so their cells are written, but the header line is out by then
+#[derive(Default)]
+struct TableHeader {
+    columns: Vec<String>,
+    known: HashSet<String>,
+    written: bool,
+}
 
-    std::fs::write(output,content)?;
+impl TableHeader {
+    fn set_columns(&mut self, columns: Vec<String>) {
+        self.known = columns.iter().cloned().collect();
+        self.columns = columns;
+    }
+
+    fn extend(&mut self, row: &Value) {
+        if let Value::Object(map) = row {
+            for key in map.keys() {
+                if !self.known.contains(key) {
+                    self.known.insert(key.clone());
+                    self.columns.push(key.clone());
+                }
+            }
+        }
+    }
+}
+
+/// CSV re-delimited with the output delimiter
+struct CsvWriter<W: Write> {
+    writer: csv::Writer<W>,
+    header: TableHeader,
+}
+
+/// GitHub flavored markdown table
+struct MarkdownWriter<W: Write> {
+    writer: W,
+    header: TableHeader,
+}
+
+/// HTML table
+struct HtmlWriter<W: Write> {
+    writer: W,
+    header: TableHeader,
+}
+
+impl<W: Write> RowWriter for TomlWriter<W> {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        if self.count > 0 {
+            self.writer.write_all(b"\n")?;
+        }
+        // TOML has no null, so null fields are left out
+        let row = without_nulls(row);
+        let content = toml::to_string(&TomlRow { rows: [&row] })?;
+        self.writer.write_all(content.as_bytes())?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if self.count == 0 {
+            self.writer.write_all(b"rows = []\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> RowWriter for NdjsonWriter<W> {
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, row)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> CsvWriter<W> {
+    fn write_header(&mut self) -> anyhow::Result<()> {
+        // An input without header and without rows has no columns at all
+        if !self.header.written && !self.header.columns.is_empty() {
+            self.writer.write_record(&self.header.columns)?;
+        }
+        self.header.written = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> RowWriter for CsvWriter<W> {
+    fn set_columns(&mut self, columns: Vec<String>) {
+        self.header.set_columns(columns);
+    }
+
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        self.header.extend(row);
+        self.write_header()?;
+        self.writer.write_record(row_cells(&self.header.columns, row).iter().map(|cell| cell.as_ref()))?;
+        Ok(())
+    }
 
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.write_header()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> MarkdownWriter<W> {
+    fn write_header(&mut self) -> anyhow::Result<()> {
+        if !self.header.written && !self.header.columns.is_empty() {
+            let columns = &self.header.columns;
+            write_markdown_line(&mut self.writer, columns.iter().map(String::as_str))?;
+            write_markdown_line(&mut self.writer, columns.iter().map(|_| "---"))?;
+        }
+        self.header.written = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> RowWriter for MarkdownWriter<W> {
+    fn set_columns(&mut self, columns: Vec<String>) {
+        self.header.set_columns(columns);
+    }
+
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        self.header.extend(row);
+        self.write_header()?;
+        let cells = row_cells(&self.header.columns, row);
+        write_markdown_line(&mut self.writer, cells.iter().map(|cell| cell.as_ref()))
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.write_header()?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> HtmlWriter<W> {
+    fn write_header(&mut self) -> anyhow::Result<()> {
+        if !self.header.written && !self.header.columns.is_empty() {
+            self.writer.write_all(b"<table>\n  <thead>\n")?;
+            write_html_line(&mut self.writer, "th", self.header.columns.iter().map(String::as_str))?;
+            self.writer.write_all(b"  </thead>\n  <tbody>\n")?;
+        }
+        self.header.written = true;
+        Ok(())
+    }
+}
+
+impl<W: Write> RowWriter for HtmlWriter<W> {
+    fn set_columns(&mut self, columns: Vec<String>) {
+        self.header.set_columns(columns);
+    }
+
+    fn write_row(&mut self, row: &Value) -> anyhow::Result<()> {
+        self.header.extend(row);
+        self.write_header()?;
+        let cells = row_cells(&self.header.columns, row);
+        write_html_line(&mut self.writer, "td", cells.iter().map(|cell| cell.as_ref()))
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.write_header()?;
+        let close: &[u8] = if self.header.columns.is_empty() {
+            b"<table>\n</table>\n"
+        } else {
+            b"  </tbody>\n</table>\n"
+        };
+        self.writer.write_all(close)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), without_nulls(v)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().filter(|v| !v.is_null()).map(without_nulls).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Cells of `row` in header order, missing fields are empty
+fn row_cells<'a>(header: &[String], row: &'a Value) -> Vec<Cow<'a, str>> {
+    header
+        .iter()
+        .map(|name| match row.get(name) {
+            Some(value) => cell_text(value),
+            None => Cow::Borrowed(""),
+        })
+        .collect()
+}
+
+/// Plain text of a value for tabular outputs: strings unquoted, null empty, anything else as JSON
+pub fn cell_text(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::String(s) => Cow::Borrowed(s),
+        Value::Null => Cow::Borrowed(""),
+        other => Cow::Owned(other.to_string()),
+    }
+}
+
+fn write_markdown_line<'a>(writer: &mut impl Write, cells: impl Iterator<Item = &'a str>) -> anyhow::Result<()> {
+    writer.write_all(b"|")?;
+    for cell in cells {
+        let cell = cell.replace('|', "\\|").replace("\r\n", "<br>").replace('\n', "<br>");
+        write!(writer, " {} |", cell)?;
+    }
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+fn write_html_line<'a>(writer: &mut impl Write, tag: &str, cells: impl Iterator<Item = &'a str>) -> anyhow::Result<()> {
+    writer.write_all(b"    <tr>")?;
+    for cell in cells {
+        write!(writer, "<{tag}>{}</{tag}>", html_escape(cell))?;
+    }
+    writer.write_all(b"</tr>\n")?;
     Ok(())
 }
+
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn row_writer<'a, W: Write + 'a>(format: OutputFormat, delimiter: char, writer: W) -> Box<dyn RowWriter + 'a> {
+    match format {
+        OutputFormat::Json => Box::new(JsonWriter { writer, count: 0 }),
+        OutputFormat::Yaml => Box::new(YamlWriter { writer, count: 0 }),
+        OutputFormat::Toml => Box::new(TomlWriter { writer, count: 0 }),
+        OutputFormat::Ndjson => Box::new(NdjsonWriter { writer }),
+        OutputFormat::Csv => {
+            // Rows may bring columns after the header line, see `TableHeader`
+            let writer = csv::WriterBuilder::new().delimiter(delimiter as u8).flexible(true).from_writer(writer);
+            Box::new(CsvWriter { writer, header: TableHeader::default() })
+        },
+        OutputFormat::Md => Box::new(MarkdownWriter { writer, header: TableHeader::default() }),
+        OutputFormat::Html => Box::new(HtmlWriter { writer, header: TableHeader::default() }),
+        OutputFormat::Parquet | OutputFormat::Arrow => Box::new(ColumnarWriter::new(format, writer)),
+    }
+}
+
+/// Flush the end of the output. The JSON array is the only format without a trailing newline, it gets one on
+/// stdout to keep the shell prompt off the closing bracket, while files keep the exact `to_string_pretty` bytes
+pub fn end_output<W: Write>(writer: &mut W, format: OutputFormat, output: &str) -> anyhow::Result<()> {
+    if output == "-" && format == OutputFormat::Json {
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Build a `csv::Reader` honoring the encoding, delimiter, header, quoting and comment options
+pub fn csv_reader<R: Read>(opts: &CsvReadOpts, reader: R) -> Reader<DecodeReaderBytes<R, Vec<u8>>> {
+    // The cli only accepts ASCII for these characters, so the casts are lossless
+    ReaderBuilder::new()
+        .delimiter(opts.delimiter as u8)
+        .has_headers(opts.header)
+        .quote(opts.quote as u8)
+        .double_quote(opts.escape.is_none())
+        .escape(opts.escape.map(|c| c as u8))
+        .comment(opts.comment.map(|c| c as u8))
+        .flexible(opts.flexible)
+        .from_reader(decode_input(opts.encoding, reader))
+}
+
+/// Transcode the input to UTF-8. A BOM wins over the given encoding and is dropped,
+/// so the first header is never `\u{feff}Name`. UTF-8 input passes through unchecked
+pub fn decode_input<R: Read>(encoding: InputEncoding, reader: R) -> DecodeReaderBytes<R, Vec<u8>> {
+    let encoding = match encoding {
+        InputEncoding::Label(encoding) if encoding != UTF_8 => Some(encoding),
+        _ => None,
+    };
+    DecodeReaderBytesBuilder::new().encoding(encoding).bom_override(true).strip_bom(true).build(reader)
+}
+
+/// Column names of the input, empty when there is no header row
+pub fn csv_headers<R: Read>(opts: &CsvReadOpts, reader: &mut Reader<R>) -> anyhow::Result<Vec<String>> {
+    if opts.header {
+        Ok(reader.headers()?.iter().map(String::from).collect())
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Name of the i-th column, col1..colN when there is no header
+pub fn column_name(headers: &[String], i: usize) -> String {
+    match headers.get(i) {
+        Some(name) => name.clone(),
+        None => format!("col{}", i + 1),
+    }
+}
+
+/// Positions of the named columns, col1..colN when there is no header
+pub fn column_positions(headers: &[String], names: &[String], file: &str) -> anyhow::Result<Vec<usize>> {
+    names
+        .iter()
+        .map(|name| {
+            let position = if headers.is_empty() {
+                name.strip_prefix("col").and_then(|n| n.parse::<usize>().ok()).filter(|n| *n > 0).map(|n| n - 1)
+            } else {
+                headers.iter().position(|header| header == name)
+            };
+            position.ok_or_else(|| anyhow::anyhow!("Column '{}' not found in {}", name, file))
+        })
+        .collect()
+}
+
+/// Cells at the given positions, empty when the row is too short
+pub fn key_values(record: &StringRecord, positions: &[usize]) -> Vec<String> {
+    positions.iter().map(|i| record.get(*i).unwrap_or("").to_string()).collect()
+}
+
+/// Records read ahead to infer the column types with --infer
+pub const INFER_ROWS: usize = 1000;
+
+/// The first `INFER_ROWS` records when --infer needs them to type the columns, none otherwise
+pub fn read_sample<R: Read>(opts: &CsvOpts, reader: &mut Reader<R>) -> anyhow::Result<Vec<StringRecord>> {
+    if !opts.infer {
+        return Ok(Vec::new());
+    }
+    Ok(reader.records().take(INFER_ROWS).collect::<Result<_, _>>()?)
+}
+
+/// Turns records into JSON objects, applying type inference and explicit column types
+pub struct RowBuilder {
+    headers: Vec<String>,
+    /// Inferred type of every column, `None` without --infer
+    inferred: Option<Vec<Option<CellType>>>,
+    schema: HashMap<String, CellType>,
+}
+
+impl RowBuilder {
+    /// With `infer`, each column gets the type that fits all its cells in `sample`, so a column never mixes types
+    pub fn new(headers: Vec<String>, infer: bool, sample: &[StringRecord], schema: &[(String, CellType)]) -> anyhow::Result<Self> {
+        // Without a header row the col1..colN names are only known once rows arrive
+        if !headers.is_empty() {
+            if let Some((name, _)) = schema.iter().find(|(name, _)| !headers.contains(name)) {
+                anyhow::bail!("Schema column '{}' not found in header", name);
+            }
+        }
+        let schema = schema.iter().cloned().collect();
+        let inferred = infer.then(|| infer_column_types(sample));
+        Ok(RowBuilder { headers, inferred, schema })
+    }
+
+    /// Build the object for the `row`-th data record (1-based), naming fields without a header col1..colN
+    pub fn build(&self, row: usize, record: &StringRecord) -> anyhow::Result<Value> {
+        let mut map = Map::with_capacity(record.len());
+        for (i, field) in record.iter().enumerate() {
+            let key = column_name(&self.headers, i);
+            let value = match self.schema.get(&key) {
+                Some(ty) => parse_value(field, *ty)
+                    .map_err(|e| anyhow::anyhow!("row {}, column '{}': {}", row, key, e))?,
+                None => match &self.inferred {
+                    Some(types) => column_value(field, types.get(i).copied().flatten()),
+                    None => Value::String(field.into()),
+                },
+            };
+            map.insert(key, value);
+        }
+        Ok(Value::Object(map))
+    }
+}
+
+/// Everything between a parsed record and the writer: building the row, then filtering and projecting it
+pub struct RowPipeline {
+    query: Query,
+    builder: RowBuilder,
+    decoder: Option<RecordDecoder>,
+    unflatten: bool,
+}
+
+impl RowPipeline {
+    /// `sample` are the first records as given by `read_sample`, they still have to be processed
+    pub fn new(opts: &CsvOpts, headers: Vec<String>, sample: &[StringRecord]) -> anyhow::Result<Self> {
+        let query = Query::from_opts(opts)?;
+        // With a schema file, rows are keyed by its field names instead of the header
+        let decoder = opts.schema_file.as_deref().map(|path| RecordSchema::load(path)?.decoder(&headers)).transpose()?;
+        match &decoder {
+            Some(decoder) => query.check_columns(&decoder.names())?,
+            None => query.check_columns(&headers)?,
+        }
+        let builder = RowBuilder::new(headers, opts.infer, sample, &opts.schema)?;
+        Ok(RowPipeline { query, builder, decoder, unflatten: opts.unflatten })
+    }
+
+    /// Names of the output columns as far as the header tells, rows without a header add col1..colN later
+    pub fn columns(&self) -> Vec<String> {
+        let columns = match &self.decoder {
+            _ if !self.query.select.is_empty() => self.query.select.clone(),
+            Some(decoder) => decoder.names(),
+            None => self.builder.headers.clone(),
+        };
+        if !self.unflatten {
+            return columns;
+        }
+        // Nested columns are written under their top-level key
+        let mut top = Vec::new();
+        for column in &columns {
+            let key = column.split('.').next().unwrap_or(column);
+            if !top.iter().any(|k| k == key) {
+                top.push(key.to_string());
+            }
+        }
+        top
+    }
+
+    /// The output row for the `row`-th data record (1-based), `None` when it is filtered out
+    pub fn process(&self, row: usize, record: &StringRecord) -> anyhow::Result<Option<Value>> {
+        let value = match &self.decoder {
+            Some(decoder) => decoder.decode(row, record)?,
+            None => self.builder.build(row, record)?,
+        };
+        if !self.query.matches(&value) {
+            return Ok(None);
+        }
+        // The sort column need not be selected, so rows to sort are projected by the sink after sorting
+        if self.query.needs_buffer() {
+            return Ok(Some(value));
+        }
+        Ok(Some(self.query.project(value)))
+    }
+}
+
+/// Writes processed rows, holding them back only when they have to be sorted first
+pub struct RowSink<'a> {
+    pipeline: &'a RowPipeline,
+    writer: Box<dyn RowWriter + 'a>,
+    sorted: Vec<Value>,
+    written: usize,
+    limit: usize,
+}
+
+impl<'a> RowSink<'a> {
+    pub fn new(pipeline: &'a RowPipeline, mut writer: Box<dyn RowWriter + 'a>) -> Self {
+        writer.set_columns(pipeline.columns());
+        let limit = pipeline.query.limit.unwrap_or(usize::MAX);
+        RowSink { pipeline, writer, sorted: Vec::new(), written: 0, limit }
+    }
+
+    /// Whether more rows are wanted, false once the limit is written
+    pub fn wants_more(&self) -> bool {
+        self.written < self.limit
+    }
+
+    pub fn push(&mut self, value: Value) -> anyhow::Result<()> {
+        if self.pipeline.query.needs_buffer() {
+            self.sorted.push(value);
+        } else if self.wants_more() {
+            self.write(value)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        let mut sorted = std::mem::take(&mut self.sorted);
+        self.pipeline.query.sort(&mut sorted);
+        for value in sorted.into_iter().take(self.limit) {
+            self.write(self.pipeline.query.project(value))?;
+        }
+        self.writer.finish()
+    }
+
+    fn write(&mut self, value: Value) -> anyhow::Result<()> {
+        // Querying works on the flat header names, nesting only happens on the way out
+        let value = match value {
+            Value::Object(map) if self.pipeline.unflatten => unflatten_object(map)?,
+            value => value,
+        };
+        self.writer.write_row(&value)?;
+        self.written += 1;
+        Ok(())
+    }
+}
+
+/// Stream CSV records from `reader` to `writer` in the given format without buffering all rows,
+/// unless sorting is requested
+pub fn convert_csv<R: Read, W: Write>(reader: R, writer: W, opts: &CsvOpts) -> anyhow::Result<()> {
+    let mut reader = csv_reader(&opts.read, reader);
+    let headers = csv_headers(&opts.read, &mut reader)?;
+    let sample = read_sample(opts, &mut reader)?;
+    let pipeline = RowPipeline::new(opts, headers, &sample)?;
+    let mut sink = RowSink::new(&pipeline, row_writer(opts.format, opts.output_delimiter, writer));
+    let mut row = 0;
+    for record in &sample {
+        if !sink.wants_more() {
+            break;
+        }
+        row += 1;
+        if let Some(value) = pipeline.process(row, record)? {
+            sink.push(value)?;
+        }
+    }
+    // Reuse a single record buffer instead of allocating one per row
+    let mut record = StringRecord::new();
+    while sink.wants_more() && reader.read_record(&mut record)? {
+        row += 1;
+        if let Some(value) = pipeline.process(row, &record)? {
+            sink.push(value)?;
+        }
+    }
+    sink.finish()
+}
+
+pub fn process_csv(opts: &CsvOpts) -> anyhow::Result<()> {
+    let output = match &opts.output {
+        _ if opts.stdout => "-".to_string(),
+        Some(output) => output.clone(),
+        // Default output file name
+        None => format!("output.{}", opts.format),
+    };
+    // Creating the output truncates it, which would destroy an input of the same path
+    let output_path = Path::new(&output);
+    if output != "-" && opts.input != "-" && output_path.exists() && output_path.canonicalize()? == Path::new(&opts.input).canonicalize()? {
+        anyhow::bail!("Output file '{}' is the same as the input file", output);
+    }
+    if opts.sheet.is_some() && !is_spreadsheet(&opts.input) {
+        anyhow::bail!("--sheet needs a spreadsheet input such as .xlsx, not '{}'", opts.input);
+    }
+    let mut writer;
+    if is_spreadsheet(&opts.input) {
+        writer = get_writer(&output)?;
+        convert_sheet(&opts.input, &mut writer, opts)?;
+    } else {
+        let reader = get_reader(&opts.input)?;
+        writer = get_writer(&output)?;
+        convert_csv_parallel(reader, &mut writer, opts, CHUNK_SIZE)?;
+    }
+    end_output(&mut writer, opts.format, &output)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    fn opts(args: &[&str]) -> CsvOpts {
+        CsvOpts::parse_from(["csv", "-i", "-"].iter().chain(args))
+    }
+
+    fn buffered(input: &str) -> anyhow::Result<Vec<Value>> {
+        let mut reader = Reader::from_path(input)?;
+        let headers = reader.headers()?.clone();
+        let mut ret = Vec::new();
+        for result in reader.records() {
+            let record = result?;
+            ret.push(headers.iter().zip(record.iter()).collect::<Value>());
+        }
+        Ok(ret)
+    }
+
+    fn convert(input: &[u8], args: &[&str]) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        convert_csv(input, &mut output, &opts(args))?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_convert_csv_json_matches_buffered() -> anyhow::Result<()> {
+        let input = "assets/juventus.csv";
+        let expected = serde_json::to_string_pretty(&buffered(input)?)?;
+        assert_eq!(convert(&std::fs::read(input)?, &[])?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_yaml_matches_buffered() -> anyhow::Result<()> {
+        let input = "assets/juventus.csv";
+        let expected = serde_yaml::to_string(&buffered(input)?)?;
+        assert_eq!(convert(&std::fs::read(input)?, &["--format", "yaml"])?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_empty() -> anyhow::Result<()> {
+        let input = b"Name,Position\n";
+        assert_eq!(convert(input, &[])?, "[]");
+        assert_eq!(convert(input, &["--format", "yaml"])?, "[]\n");
+        // Tabular formats still name the columns
+        assert_eq!(convert(input, &["--format", "csv"])?, "Name,Position\n");
+        assert_eq!(convert(input, &["--format", "md"])?, "| Name | Position |\n| --- | --- |\n");
+        assert_eq!(
+            convert(input, &["--format", "html"])?,
+            "<table>\n  <thead>\n    <tr><th>Name</th><th>Position</th></tr>\n  </thead>\n  <tbody>\n  </tbody>\n</table>\n"
+        );
+        assert_eq!(convert(input, &["--format", "csv", "--select", "Position"])?, "Position\n");
+        assert_eq!(convert(b"", &["--format", "csv", "--no-header"])?, "");
+        assert_eq!(convert(b"", &["--format", "html", "--no-header"])?, "<table>\n</table>\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_tabular_columns() -> anyhow::Result<()> {
+        // A short first row keeps every header column, a later long row keeps its extra field
+        let input = b"a,b\n1\n1,2,3\n";
+        assert_eq!(convert(input, &["--format", "csv", "--flexible"])?, "a,b\n1,\n1,2,3\n");
+        assert_eq!(convert(input, &["--format", "md", "--flexible"])?, "| a | b |\n| --- | --- |\n| 1 |  |\n| 1 | 2 | 3 |\n");
+        // Extra fields of the first row join the header as colN
+        assert_eq!(convert(b"a\n1,2\n", &["--format", "csv", "--flexible"])?, "a,col2\n1,2\n");
+        assert_eq!(convert(b"a.x,a.y,b\n1,2,3\n", &["--format", "csv", "--unflatten"])?, "a,b\n\"{\"\"x\"\":\"\"1\"\",\"\"y\"\":\"\"2\"\"}\",3\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_end_output() -> anyhow::Result<()> {
+        let end = |format, output| -> anyhow::Result<Vec<u8>> {
+            let mut buffer = b"[]".to_vec();
+            end_output(&mut buffer, format, output)?;
+            Ok(buffer)
+        };
+        assert_eq!(end(OutputFormat::Json, "-")?, b"[]\n");
+        assert_eq!(end(OutputFormat::Json, "output.json")?, b"[]");
+        assert_eq!(end(OutputFormat::Yaml, "-")?, b"[]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_matches_fixtures() -> anyhow::Result<()> {
+        let input = std::fs::read("fixtures/players.csv")?;
+        for (args, expected) in [
+            (&["--format", "json"][..], "fixtures/players.json"),
+            (&["--format", "yaml"], "fixtures/players.yaml"),
+            (&["--format", "toml"], "fixtures/players.toml"),
+            (&["--format", "ndjson"], "fixtures/players.ndjson"),
+            (&["--format", "csv", "--output-delimiter", ";"], "fixtures/players_semicolon.csv"),
+            (&["--format", "md"], "fixtures/players.md"),
+            (&["--format", "html"], "fixtures/players.html"),
+        ] {
+            assert_eq!(convert(&input, args)?, std::fs::read_to_string(expected)?, "{expected}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_round_trip() -> anyhow::Result<()> {
+        let input = std::fs::read("fixtures/players.csv")?;
+        let expected = buffered("fixtures/players.csv")?;
+
+        let json: Vec<Value> = serde_json::from_str(&convert(&input, &["--format", "json"])?)?;
+        assert_eq!(json, expected);
+
+        let yaml: Vec<Value> = serde_yaml::from_str(&convert(&input, &["--format", "yaml"])?)?;
+        assert_eq!(yaml, expected);
+
+        #[derive(Deserialize)]
+        struct TomlRows {
+            rows: Vec<Value>,
+        }
+        let toml: TomlRows = toml::from_str(&convert(&input, &["--format", "toml"])?)?;
+        assert_eq!(toml.rows, expected);
+
+        let ndjson = convert(&input, &["--format", "ndjson"])?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Value>, _>>()?;
+        assert_eq!(ndjson, expected);
+
+        let csv = convert(&input, &["--format", "csv", "--output-delimiter", ";"])?;
+        let back = convert(csv.as_bytes(), &["-d", ";"])?;
+        assert_eq!(rows(&back)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_escapes_tables() -> anyhow::Result<()> {
+        let input = b"Name,Note\nA|B,<b>&</b>\n";
+        assert_eq!(convert(input, &["--format", "md"])?, "| Name | Note |\n| --- | --- |\n| A\\|B | <b>&</b> |\n");
+        let html = convert(input, &["--format", "html"])?;
+        assert!(html.contains("<td>A|B</td><td>&lt;b&gt;&amp;&lt;/b&gt;</td>"));
+        assert_eq!(convert(b"Name\n", &["--format", "toml"])?, "rows = []\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_infer_and_schema() -> anyhow::Result<()> {
+        let input = b"Name,Kit Number,Height,Active,Joined,Zip\nBuffon,77,1.92,true,2001-07-03,007\nPerin,,,FALSE,,10121\n";
+        let output = convert(input, &["--infer"])?;
+        // One type per column, "007" makes the whole Zip column text
+        assert_eq!(
+            rows(&output)?,
+            vec![
+                serde_json::json!({"Name": "Buffon", "Kit Number": 77, "Height": 1.92, "Active": true, "Joined": "2001-07-03", "Zip": "007"}),
+                serde_json::json!({"Name": "Perin", "Kit Number": null, "Height": null, "Active": false, "Joined": null, "Zip": "10121"}),
+            ]
+        );
+        let output = convert(b"Height\n2\n1.5\n", &["--infer"])?;
+        assert_eq!(rows(&output)?, vec![serde_json::json!({"Height": 2.0}), serde_json::json!({"Height": 1.5})]);
+
+        // Explicit types win over inference, and apply without --infer
+        let output = convert(input, &["--infer", "--schema", "Zip:string,Kit Number:float"])?;
+        assert_eq!(rows(&output)?[1]["Zip"], "10121");
+        assert_eq!(rows(&output)?[0]["Kit Number"], 77.0);
+        let output = convert(input, &["--schema", "Zip:int"])?;
+        assert_eq!(rows(&output)?[0], serde_json::json!({"Name": "Buffon", "Kit Number": "77", "Height": "1.92", "Active": "true", "Joined": "2001-07-03", "Zip": 7}));
+
+        // TOML has no null, those fields are dropped
+        let output = convert(input, &["--infer", "--format", "toml"])?;
+        assert!(output.ends_with("[[rows]]\nName = \"Perin\"\nActive = false\nZip = \"10121\"\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_schema_errors() {
+        let input = b"Name,Kit Number\nBuffon,77\nPerin,abc\n";
+        let err = convert(input, &["--schema", "Kit Number:int"]).unwrap_err();
+        assert_eq!(err.to_string(), "row 2, column 'Kit Number': 'abc' is not an integer");
+        let err = convert(input, &["--schema", "Number:int"]).unwrap_err();
+        assert_eq!(err.to_string(), "Schema column 'Number' not found in header");
+        assert!(convert(b"1,x\n", &["--no-header", "--schema", "col1:int"]).is_ok());
+    }
+
+    #[test]
+    fn test_convert_csv_unflatten() -> anyhow::Result<()> {
+        let input = b"name,address.city,address.zip,tags.0,tags.1\nBuffon,Turin,10121,keeper,captain\nPerin,Genoa,,keeper,\n";
+        let output = convert(input, &["--unflatten", "--infer", "--format", "ndjson", "--sort-by", "address.city"])?;
+        assert_eq!(
+            output,
+            "{\"name\":\"Perin\",\"address\":{\"city\":\"Genoa\",\"zip\":null},\"tags\":[\"keeper\",null]}\n\
+             {\"name\":\"Buffon\",\"address\":{\"city\":\"Turin\",\"zip\":10121},\"tags\":[\"keeper\",\"captain\"]}\n"
+        );
+        assert!(convert(b"a,a.b\n1,2\n", &["--unflatten"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_encodings() -> anyhow::Result<()> {
+        let text = "名前,都市\n布冯,都灵\n";
+        let expected = "[\n  {\n    \"名前\": \"布冯\",\n    \"都市\": \"都灵\"\n  }\n]";
+        let (gbk, _, _) = encoding_rs::GBK.encode(text);
+        assert_eq!(convert(&gbk, &["--encoding", "gbk"])?, expected);
+        assert!(convert(&gbk, &[]).is_err());
+
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("名前\n東京\n");
+        assert_eq!(convert(&sjis, &["--encoding", "shift_jis", "--format", "csv"])?, "名前\n東京\n");
+
+        // BOMs are detected whatever the encoding option says, and never end up in the first key
+        let utf16 = [&[0xff, 0xfe][..], &text.encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>()].concat();
+        assert_eq!(convert(&utf16, &[])?, expected);
+        assert_eq!(convert(&utf16, &["--encoding", "gbk"])?, expected);
+        let utf8 = [b"\xef\xbb\xbf", text.as_bytes()].concat();
+        assert_eq!(convert(&utf8, &["--encoding", "utf-8"])?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_schema_file() -> anyhow::Result<()> {
+        let input = b"Name,Position,DOB,Nationality,Kit\nBuffon,Goalkeeper,\"Jan 28, 1978 (41)\",Italy,77\n";
+        let args = ["--schema-file", "fixtures/players.schema.yaml", "--format", "ndjson", "--where", "kit > 10"];
+        assert_eq!(
+            convert(input, &args)?,
+            "{\"name\":\"Buffon\",\"position\":\"Goalkeeper\",\"dob\":\"Jan 28, 1978 (41)\",\"nationality\":\"Italy\",\"kit\":77,\"captain\":false}\n"
+        );
+        let input = b"Name,Position,DOB,Nationality,Kit Number\nBuffon,Goalkeeper,,Italy,abc\n";
+        let err = convert(input, &["--schema-file", "fixtures/players.schema.yaml"]).unwrap_err();
+        assert_eq!(err.to_string(), "row 1, column 'Kit Number': 'abc' is not a u8");
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_query() -> anyhow::Result<()> {
+        let input = std::fs::read("assets/juventus.csv")?;
+        let output = convert(
+            &input,
+            &["--select", "Name,Kit Number", "--where", "Position == 'Goalkeeper'", "--sort-by", "Kit Number", "--desc", "--limit", "2"],
+        )?;
+        assert_eq!(
+            rows(&output)?,
+            vec![
+                serde_json::json!({"Name": "Gianluigi Buffon", "Kit Number": "77"}),
+                serde_json::json!({"Name": "Mattia Perin", "Kit Number": "37"}),
+            ]
+        );
+
+        // Without sorting the limit stops reading early
+        let output = convert(&input, &["--select", "Name", "--where", "`Kit Number` > 20 and Nationality != 'Italy'", "--limit", "1"])?;
+        assert_eq!(rows(&output)?, vec![serde_json::json!({"Name": "Merih Demiral"})]);
+
+        // The sort column does not have to be selected
+        let output = convert(&input, &["--select", "Name", "--sort-by", "Kit Number", "--desc", "--limit", "2"])?;
+        assert_eq!(rows(&output)?, vec![serde_json::json!({"Name": "Gianluigi Buffon"}), serde_json::json!({"Name": "Mattia Perin"})]);
+
+        assert!(convert(&input, &["--select", "Club"]).is_err());
+        assert!(convert(&input, &["--where", "Club == 'Juventus'"]).is_err());
+        assert!(convert(&input, &["--where", "Name =="]).is_err());
+        Ok(())
+    }
+
+    fn rows(output: &str) -> anyhow::Result<Vec<Value>> {
+        Ok(serde_json::from_str(output)?)
+    }
+
+    #[test]
+    fn test_convert_csv_delimiter() -> anyhow::Result<()> {
+        let input = b"Name\tKit Number\nBuffon\t77\n";
+        let output = convert(input, &["-d", "\\t"])?;
+        assert_eq!(rows(&output)?, vec![serde_json::json!({"Name": "Buffon", "Kit Number": "77"})]);
+
+        let input = b"Name;Kit Number\n'Buffon; Gianluigi';77\n";
+        let output = convert(input, &["-d", ";", "--quote", "'"])?;
+        assert_eq!(rows(&output)?, vec![serde_json::json!({"Name": "Buffon; Gianluigi", "Kit Number": "77"})]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_no_header() -> anyhow::Result<()> {
+        let input = b"Buffon,77\nPerin,37\n";
+        let output = convert(input, &["--no-header"])?;
+        assert_eq!(
+            rows(&output)?,
+            vec![
+                serde_json::json!({"col1": "Buffon", "col2": "77"}),
+                serde_json::json!({"col1": "Perin", "col2": "37"}),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_escape_comment_flexible() -> anyhow::Result<()> {
+        let input = b"# exported 2019\nName,Note\n\"Buffon\",\"says \\\"hi\\\"\"\nPerin,,extra\n";
+        assert!(convert(input, &["--comment", "#", "--escape", "\\"]).is_err());
+        let output = convert(input, &["--comment", "#", "--escape", "\\", "--flexible"])?;
+        assert_eq!(
+            rows(&output)?,
+            vec![
+                serde_json::json!({"Name": "Buffon", "Note": "says \"hi\""}),
+                serde_json::json!({"Name": "Perin", "Note": "", "col3": "extra"}),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, io::{self, Read, Write}};
+
+use csv::StringRecord;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{cli::{CsvDiffOpts, CsvReadOpts}, get_reader};
+
+use super::{
+    csv_convert::{cell_text, column_name, column_positions, csv_headers, csv_reader, end_output, key_values, row_writer},
+    csv_show::write_table,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One differing row. Added rows only have `after` and removed rows only `before`,
+/// changed rows have both, holding just the fields that changed
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DiffEntry {
+    pub change: Change,
+    pub key: Map<String, Value>,
+    pub before: Option<Map<String, Value>>,
+    pub after: Option<Map<String, Value>>,
+}
+
+/// The non key fields of a record
+fn fields(headers: &[String], record: &StringRecord, key: &[String]) -> Map<String, Value> {
+    record
+        .iter()
+        .enumerate()
+        .map(|(i, cell)| (column_name(headers, i), Value::String(cell.into())))
+        .filter(|(name, _)| !key.contains(name))
+        .collect()
+}
+
+/// Compare `new` against `old`, loading only the old rows into memory.
+/// Added and changed rows come in the order of the new file, followed by the removed rows
+pub fn diff_csv<A: Read, B: Read>(old: A, new: B, opts: &CsvReadOpts, key: &[String]) -> anyhow::Result<Vec<DiffEntry>> {
+    let mut old = csv_reader(opts, old);
+    let old_headers = csv_headers(opts, &mut old)?;
+    let old_key = column_positions(&old_headers, key, "old file")?;
+    let mut old_rows = Vec::new();
+    let mut index = HashMap::new();
+    for record in old.records() {
+        let record = record?;
+        if let Some(row) = index.insert(key_values(&record, &old_key), old_rows.len()) {
+            anyhow::bail!("Duplicate key in old file, rows {} and {}", row + 1, old_rows.len() + 1);
+        }
+        old_rows.push(Some(record));
+    }
+
+    let mut new = csv_reader(opts, new);
+    let new_headers = csv_headers(opts, &mut new)?;
+    let new_key = column_positions(&new_headers, key, "new file")?;
+    let key_map = |values: Vec<String>| key.iter().cloned().zip(values.into_iter().map(Value::String)).collect::<Map<_, _>>();
+    let mut seen = HashMap::new();
+    let mut entries = Vec::new();
+    for (row, record) in new.records().enumerate() {
+        let record = record?;
+        let values = key_values(&record, &new_key);
+        if let Some(first) = seen.insert(values.clone(), row) {
+            anyhow::bail!("Duplicate key in new file, rows {} and {}", first + 1, row + 1);
+        }
+        let after = fields(&new_headers, &record, key);
+        // Taking the old row marks it as matched, whatever is left was removed
+        let Some(old_record) = index.get(&values).and_then(|i| old_rows[*i].take()) else {
+            entries.push(DiffEntry { change: Change::Added, key: key_map(values), before: None, after: Some(after) });
+            continue;
+        };
+        let before = fields(&old_headers, &old_record, key);
+        let mut changed_before = Map::new();
+        let mut changed_after = Map::new();
+        // Columns only in one of the files count as null in the other
+        let names = before.keys().chain(after.keys().filter(|name| !before.contains_key(*name)));
+        for name in names {
+            let (old_value, new_value) = (before.get(name).unwrap_or(&Value::Null), after.get(name).unwrap_or(&Value::Null));
+            if old_value != new_value {
+                changed_before.insert(name.clone(), old_value.clone());
+                changed_after.insert(name.clone(), new_value.clone());
+            }
+        }
+        if !changed_before.is_empty() {
+            entries.push(DiffEntry {
+                change: Change::Changed,
+                key: key_map(values),
+                before: Some(changed_before),
+                after: Some(changed_after),
+            });
+        }
+    }
+    for record in old_rows.into_iter().flatten() {
+        let values = key_values(&record, &old_key);
+        entries.push(DiffEntry {
+            change: Change::Removed,
+            key: key_map(values),
+            before: Some(fields(&old_headers, &record, key)),
+            after: None,
+        });
+    }
+    Ok(entries)
+}
+
+/// One line per changed field, added and removed rows on a single line
+fn write_diff_table<W: Write>(writer: &mut W, entries: &[DiffEntry]) -> anyhow::Result<()> {
+    let headers = ["change", "key", "column", "before", "after"].map(String::from);
+    let join = |map: &Map<String, Value>| {
+        map.iter().map(|(name, value)| format!("{}={}", name, cell_text(value))).collect::<Vec<_>>().join(", ")
+    };
+    let mut rows = Vec::new();
+    for entry in entries {
+        let key = entry.key.values().map(|value| cell_text(value).into_owned()).collect::<Vec<_>>().join(", ");
+        match (entry.change, &entry.before, &entry.after) {
+            (Change::Changed, Some(before), Some(after)) => {
+                for (name, old_value) in before {
+                    let new_value = after.get(name).unwrap_or(&Value::Null);
+                    rows.push(vec![
+                        "changed".into(),
+                        key.clone(),
+                        name.clone(),
+                        cell_text(old_value).into_owned(),
+                        cell_text(new_value).into_owned(),
+                    ]);
+                }
+            },
+            (change, before, after) => {
+                let change = if change == Change::Added { "added" } else { "removed" };
+                rows.push(vec![
+                    change.into(),
+                    key,
+                    String::new(),
+                    before.as_ref().map(join).unwrap_or_default(),
+                    after.as_ref().map(join).unwrap_or_default(),
+                ]);
+            },
+        }
+    }
+    write_table(writer, &headers, &rows, 40, false)
+}
+
+pub fn process_csv_diff(opts: &CsvDiffOpts) -> anyhow::Result<()> {
+    if opts.old == "-" && opts.new == "-" {
+        anyhow::bail!("Old and new files cannot both be read from stdin");
+    }
+    let entries = diff_csv(get_reader(&opts.old)?, get_reader(&opts.new)?, &opts.read, &opts.key)?;
+    let count = |change| entries.iter().filter(|entry| entry.change == change).count();
+    eprintln!(
+        "{} added, {} removed, {} changed",
+        count(Change::Added),
+        count(Change::Removed),
+        count(Change::Changed)
+    );
+    let mut stdout = io::stdout().lock();
+    match opts.format {
+        Some(format) => {
+            {
+                let mut writer = row_writer(format, ',', &mut stdout);
+                for entry in &entries {
+                    writer.write_row(&serde_json::to_value(entry)?)?;
+                }
+                writer.finish()?;
+            }
+            end_output(&mut stdout, format, "-")?;
+        },
+        None => write_diff_table(&mut stdout, &entries)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::json;
+
+    use super::*;
+
+    const OLD: &str = "Name,Position,Kit\nBuffon,Goalkeeper,1\nPerin,Goalkeeper,37\nBonucci,Centre-Back,19\n";
+    const NEW: &str = "Name,Position,Kit\nBuffon,Goalkeeper,77\nBonucci,Centre-Back,19\nde Ligt,Centre-Back,4\n";
+
+    fn diff(old: &str, new: &str, args: &[&str]) -> anyhow::Result<Vec<DiffEntry>> {
+        let opts = CsvDiffOpts::parse_from(["diff", "-a", "-", "-b", "-"].iter().chain(args));
+        diff_csv(old.as_bytes(), new.as_bytes(), &opts.read, &opts.key)
+    }
+
+    #[test]
+    fn test_diff_csv() -> anyhow::Result<()> {
+        let entries = diff(OLD, NEW, &["--key", "Name"])?;
+        assert_eq!(
+            serde_json::to_value(&entries)?,
+            json!([
+                {"change": "changed", "key": {"Name": "Buffon"}, "before": {"Kit": "1"}, "after": {"Kit": "77"}},
+                {"change": "added", "key": {"Name": "de Ligt"}, "before": null, "after": {"Position": "Centre-Back", "Kit": "4"}},
+                {"change": "removed", "key": {"Name": "Perin"}, "before": {"Position": "Goalkeeper", "Kit": "37"}, "after": null},
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_csv_columns_and_keys() -> anyhow::Result<()> {
+        // A new column is a change from null, composite keys keep their column order
+        let entries = diff("a,b,c\n1,x,y\n", "a,b,d,c\n1,x,z,y\n", &["--key", "b,a"])?;
+        assert_eq!(
+            serde_json::to_value(&entries)?,
+            json!([{"change": "changed", "key": {"b": "x", "a": "1"}, "before": {"d": null}, "after": {"d": "z"}}])
+        );
+        assert!(diff("1,a\n2,b\n", "2,c\n", &["--no-header", "--key", "col1"])?.len() == 2);
+
+        assert!(diff(OLD, NEW, &["--key", "Club"]).is_err());
+        assert!(diff("Name\nBuffon\nBuffon\n", "Name\n", &["--key", "Name"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_diff_table() -> anyhow::Result<()> {
+        let mut output = Vec::new();
+        write_diff_table(&mut output, &diff(OLD, NEW, &["--key", "Name"])?)?;
+        let expected = "\
+change  | key     | column | before                      | after
+--------+---------+--------+-----------------------------+----------------------------
+changed | Buffon  | Kit    |                           1 |                          77
+added   | de Ligt |        |                             | Position=Centre-Back, Kit=4
+removed | Perin   |        | Position=Goalkeeper, Kit=37 |
+";
+        assert_eq!(String::from_utf8(output)?, expected);
+        Ok(())
+    }
+}
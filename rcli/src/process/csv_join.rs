@@ -0,0 +1,147 @@
+use std::{collections::HashMap, io::Read};
+
+use csv::StringRecord;
+use serde_json::{Map, Value};
+
+use crate::{cli::{CsvJoinOpts, CsvReadOpts, JoinKind}, get_reader, get_writer};
+
+use super::csv_convert::{column_positions, csv_headers, csv_reader, end_output, key_values, row_writer, RowWriter};
+
+/// Join `left` and `right` on the `on` columns, loading only the right rows into memory.
+/// The output has every left column followed by the right non key columns,
+/// suffixed with `_right` when the left side has a column of the same name
+pub fn join_csv<L: Read, R: Read>(
+    left: L,
+    right: R,
+    opts: &CsvReadOpts,
+    on: &[String],
+    how: JoinKind,
+    writer: &mut dyn RowWriter,
+) -> anyhow::Result<()> {
+    if !opts.header {
+        anyhow::bail!("csv join needs a header row to name the output columns");
+    }
+    let mut right = csv_reader(opts, right);
+    let right_headers = csv_headers(opts, &mut right)?;
+    let right_key = column_positions(&right_headers, on, "right file")?;
+    let mut right_rows = Vec::new();
+    let mut index = HashMap::<Vec<String>, Vec<usize>>::new();
+    for record in right.records() {
+        let record = record?;
+        index.entry(key_values(&record, &right_key)).or_default().push(right_rows.len());
+        right_rows.push(record);
+    }
+
+    let mut left = csv_reader(opts, left);
+    let left_headers = csv_headers(opts, &mut left)?;
+    let left_key = column_positions(&left_headers, on, "left file")?;
+    let right_columns = right_headers
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !right_key.contains(i))
+        .map(|(i, name)| {
+            let name = if left_headers.contains(name) { format!("{}_right", name) } else { name.clone() };
+            (i, name)
+        })
+        .collect::<Vec<_>>();
+    let cell = |record: Option<&StringRecord>, i: usize| match record.and_then(|record| record.get(i)) {
+        Some(cell) => Value::String(cell.into()),
+        None => Value::Null,
+    };
+    let row = |left: Option<&StringRecord>, right: Option<&StringRecord>| {
+        let mut map = Map::with_capacity(left_headers.len() + right_columns.len());
+        for (i, name) in left_headers.iter().enumerate() {
+            map.insert(name.clone(), cell(left, i));
+        }
+        for (i, name) in &right_columns {
+            map.insert(name.clone(), cell(right, *i));
+        }
+        map
+    };
+
+    writer.set_columns(left_headers.iter().chain(right_columns.iter().map(|(_, name)| name)).cloned().collect());
+
+    let mut matched = vec![false; right_rows.len()];
+    let mut record = StringRecord::new();
+    while left.read_record(&mut record)? {
+        match index.get(&key_values(&record, &left_key)) {
+            Some(rows) => {
+                for i in rows {
+                    matched[*i] = true;
+                    writer.write_row(&Value::Object(row(Some(&record), Some(&right_rows[*i]))))?;
+                }
+            },
+            None if how != JoinKind::Inner => writer.write_row(&Value::Object(row(Some(&record), None)))?,
+            None => {},
+        }
+    }
+    if how == JoinKind::Outer {
+        for (record, _) in right_rows.iter().zip(&matched).filter(|(_, matched)| !**matched) {
+            // Unmatched right rows still fill in the key columns
+            let mut map = row(None, Some(record));
+            for (name, i) in on.iter().zip(&right_key) {
+                map.insert(name.clone(), cell(Some(record), *i));
+            }
+            writer.write_row(&Value::Object(map))?;
+        }
+    }
+    writer.finish()
+}
+
+pub fn process_csv_join(opts: &CsvJoinOpts) -> anyhow::Result<()> {
+    if opts.left == "-" && opts.right == "-" {
+        anyhow::bail!("Left and right files cannot both be read from stdin");
+    }
+    let mut output = get_writer(&opts.output)?;
+    {
+        let mut writer = row_writer(opts.format, ',', &mut output);
+        join_csv(get_reader(&opts.left)?, get_reader(&opts.right)?, &opts.read, &opts.on, opts.how, writer.as_mut())?;
+    }
+    end_output(&mut output, opts.format, &opts.output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    const PLAYERS: &str = "Name,Position,Club\nBuffon,Goalkeeper,1\nPerin,Goalkeeper,1\nPogba,Midfielder,3\n";
+    const CLUBS: &str = "Club,Name,City\n1,Juventus,Turin\n1,Juventus U23,Alessandria\n2,Inter,Milan\n";
+
+    fn join(how: &str) -> anyhow::Result<String> {
+        let opts = CsvJoinOpts::parse_from(["join", "-l", "-", "-r", "-", "--on", "Club", "--how", how]);
+        let mut output = Vec::new();
+        let mut writer = row_writer(opts.format, ',', &mut output);
+        join_csv(PLAYERS.as_bytes(), CLUBS.as_bytes(), &opts.read, &opts.on, opts.how, writer.as_mut())?;
+        drop(writer);
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_join_csv() -> anyhow::Result<()> {
+        let inner = "\
+Name,Position,Club,Name_right,City
+Buffon,Goalkeeper,1,Juventus,Turin
+Buffon,Goalkeeper,1,Juventus U23,Alessandria
+Perin,Goalkeeper,1,Juventus,Turin
+Perin,Goalkeeper,1,Juventus U23,Alessandria
+";
+        assert_eq!(join("inner")?, inner);
+        assert_eq!(join("left")?, format!("{}Pogba,Midfielder,3,,\n", inner));
+        assert_eq!(join("outer")?, format!("{}Pogba,Midfielder,3,,\n,,2,Inter,Milan\n", inner));
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_csv_errors() {
+        let opts = CsvJoinOpts::parse_from(["join", "-l", "-", "-r", "-", "--on", "Nationality"]);
+        let mut output = Vec::new();
+        let mut writer = row_writer(opts.format, ',', &mut output);
+        assert!(join_csv(PLAYERS.as_bytes(), CLUBS.as_bytes(), &opts.read, &opts.on, opts.how, writer.as_mut()).is_err());
+
+        let opts = CsvJoinOpts::parse_from(["join", "-l", "-", "-r", "-", "--on", "col1", "--no-header"]);
+        assert!(join_csv(PLAYERS.as_bytes(), CLUBS.as_bytes(), &opts.read, &opts.on, opts.how, writer.as_mut()).is_err());
+    }
+}
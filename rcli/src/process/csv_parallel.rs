@@ -0,0 +1,274 @@
+use std::{io::{Read, Write}, mem};
+
+use csv::StringRecord;
+use rayon::{prelude::*, ThreadPoolBuilder};
+use serde_json::Value;
+
+use crate::cli::{CsvOpts, CsvReadOpts, InputEncoding};
+
+use super::csv_convert::{convert_csv, csv_headers, csv_reader, decode_input, read_sample, row_writer, RowPipeline, RowSink, INFER_ROWS};
+
+/// Bytes of input handed to a worker at a time
+pub const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Whole records cut from the input, `first_record` counts the records before it, header included
+struct Chunk {
+    data: Vec<u8>,
+    first_record: usize,
+}
+
+/// Cuts the input into chunks ending on record boundaries
+struct Chunker<R> {
+    reader: R,
+    quote: u8,
+    delimiter: u8,
+    pending: Vec<u8>,
+    records: usize,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    fn new(reader: R, quote: u8, delimiter: u8) -> Self {
+        Chunker { reader, quote, delimiter, pending: Vec::new(), records: 0, eof: false }
+    }
+
+    /// The next chunk of about `size` bytes, longer when a single record does not fit
+    fn next_chunk(&mut self, size: usize) -> anyhow::Result<Option<Chunk>> {
+        let mut want = size.max(1);
+        loop {
+            if !self.eof && self.pending.len() < want {
+                let missing = (want - self.pending.len()) as u64;
+                let n = (&mut self.reader).take(missing).read_to_end(&mut self.pending)?;
+                self.eof = n == 0;
+                continue;
+            }
+            let (end, records) = scan_records(&self.pending, self.quote, self.delimiter, self.eof);
+            // A chunk without a record, only blank lines, would be mistaken for the first one
+            if records == 0 && !self.eof {
+                want *= 2;
+                continue;
+            }
+            if self.pending.is_empty() {
+                return Ok(None);
+            }
+            let rest = self.pending.split_off(end);
+            let chunk = Chunk { data: mem::replace(&mut self.pending, rest), first_record: self.records };
+            self.records += records;
+            return Ok(Some(chunk));
+        }
+    }
+}
+
+/// End of the last complete record in `data` and the number of records up to it.
+/// Like the csv reader, a quote only opens a quoted field at the start of the field, elsewhere it is a plain
+/// character as in `5"11`. Inside quotes line breaks belong to the field and a doubled quote stays inside.
+/// At the end of the input the trailing record counts even without a line break.
+fn scan_records(data: &[u8], quote: u8, delimiter: u8, eof: bool) -> (usize, usize) {
+    let mut in_quotes = false;
+    // Right after a closing quote, where another quote means a doubled one
+    let mut closed = false;
+    let mut field_start = true;
+    let mut has_content = false;
+    let mut end = 0;
+    let mut records = 0;
+    let mut counted = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        if in_quotes {
+            if byte == quote {
+                in_quotes = false;
+                closed = true;
+            }
+            continue;
+        }
+        if byte == quote && (field_start || closed) {
+            in_quotes = true;
+            closed = false;
+            field_start = false;
+            has_content = true;
+            continue;
+        }
+        closed = false;
+        if byte == b'\n' || byte == b'\r' {
+            // Empty lines are skipped by the reader, so they are no record
+            if has_content {
+                records += 1;
+            }
+            has_content = false;
+            field_start = true;
+            end = i + 1;
+            counted = records;
+        } else {
+            has_content = true;
+            field_start = byte == delimiter;
+        }
+    }
+    if eof {
+        (data.len(), records + usize::from(has_content))
+    } else {
+        (end, counted)
+    }
+}
+
+/// Parse and process one chunk, numbering rows from where the chunk starts in the whole input
+fn process_chunk(chunk: &Chunk, read: &CsvReadOpts, pipeline: &RowPipeline, columns: usize) -> anyhow::Result<Vec<Value>> {
+    // Only the first chunk starts with the header
+    let first = chunk.first_record == 0;
+    let opts = CsvReadOpts { header: read.header && first, ..read.clone() };
+    let mut reader = csv_reader(&opts, chunk.data.as_slice());
+    let mut row = if read.header { chunk.first_record.saturating_sub(1) } else { chunk.first_record };
+    let mut rows = Vec::new();
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        row += 1;
+        // The reader of a later chunk has no header or first row to compare the field count with
+        if !first && !read.flexible && record.len() != columns {
+            let expected = if read.header { "the header" } else { "the first row" };
+            anyhow::bail!("row {}: found {} fields, but {} has {}", row, record.len(), expected, columns);
+        }
+        if let Some(value) = pipeline.process(row, &record)? {
+            rows.push(value);
+        }
+    }
+    Ok(rows)
+}
+
+/// Like `convert_csv`, but parses and processes chunks of about `chunk_size` bytes on `opts.jobs` threads.
+/// Chunks go out in input order, a batch of two per thread at a time, so memory stays bounded.
+/// The boundary scan only follows quotes, so input with escapes or comments is converted sequentially
+pub fn convert_csv_parallel<R: Read, W: Write>(reader: R, writer: W, opts: &CsvOpts, chunk_size: usize) -> anyhow::Result<()> {
+    let read = &opts.read;
+    if opts.jobs == 1 || read.escape.is_some() || read.comment.is_some() {
+        return convert_csv(reader, writer, opts);
+    }
+    let pool = ThreadPoolBuilder::new().num_threads(opts.jobs).build()?;
+    // Chunks are cut from the transcoded input, which the workers then read as plain UTF-8
+    let mut chunker = Chunker::new(decode_input(read.encoding, reader), read.quote as u8, read.delimiter as u8);
+    let read = &CsvReadOpts { encoding: InputEncoding::Auto, ..read.clone() };
+    let Some(first) = chunker.next_chunk(chunk_size)? else {
+        return convert_csv(std::io::empty(), writer, opts);
+    };
+    let mut first_reader = csv_reader(read, first.data.as_slice());
+    let headers = csv_headers(read, &mut first_reader)?;
+    let mut sample = read_sample(opts, &mut first_reader)?;
+    // --infer types the columns from the same rows as `convert_csv`, which may take more than one chunk
+    let mut next = vec![first];
+    while opts.infer && sample.len() < INFER_ROWS {
+        let Some(chunk) = chunker.next_chunk(chunk_size)? else {
+            break;
+        };
+        let mut reader = csv_reader(&CsvReadOpts { header: false, ..read.clone() }, chunk.data.as_slice());
+        for record in reader.records().take(INFER_ROWS - sample.len()) {
+            sample.push(record?);
+        }
+        next.push(chunk);
+    }
+    // Later chunks are checked against the field count of the header, or of the first row without one
+    let columns = if read.header {
+        headers.len()
+    } else {
+        csv_reader(read, next[0].data.as_slice()).records().next().transpose()?.map_or(0, |record| record.len())
+    };
+    let pipeline = RowPipeline::new(opts, headers, &sample)?;
+    let mut sink = RowSink::new(&pipeline, row_writer(opts.format, opts.output_delimiter, writer));
+
+    let batch_size = pool.current_num_threads() * 2;
+    while sink.wants_more() {
+        let mut batch = Vec::with_capacity(batch_size);
+        batch.append(&mut next);
+        while batch.len() < batch_size {
+            match chunker.next_chunk(chunk_size)? {
+                Some(chunk) => batch.push(chunk),
+                None => break,
+            }
+        }
+        if batch.is_empty() {
+            break;
+        }
+        let results = pool.install(|| {
+            batch.par_iter().map(|chunk| process_chunk(chunk, read, &pipeline, columns)).collect::<Vec<_>>()
+        });
+        for rows in results {
+            for value in rows? {
+                sink.push(value)?;
+            }
+        }
+    }
+    sink.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn convert(input: &str, args: &[&str], chunk_size: usize) -> anyhow::Result<String> {
+        let opts = CsvOpts::parse_from(["csv", "-i", "-"].iter().chain(args));
+        let mut output = Vec::new();
+        convert_csv_parallel(input.as_bytes(), &mut output, &opts, chunk_size)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_scan_records() {
+        assert_eq!(scan_records(b"a,b\n1,2\n3", b'"', b',', false), (8, 2));
+        assert_eq!(scan_records(b"a,b\n1,2\n3", b'"', b',', true), (9, 3));
+        assert_eq!(scan_records(b"a,\"x\ny\"\r\n\n1,\"\"\"\"\n", b'"', b',', false), (17, 2));
+        assert_eq!(scan_records(b"\"open\n", b'"', b',', false), (0, 0));
+        // A quote inside an unquoted field is a plain character and opens nothing
+        assert_eq!(scan_records(b"5\"11,\"a\nb\"\n1,2\n", b'"', b',', false), (15, 2));
+        assert_eq!(scan_records(b"x;\"a\nb\"\n", b'"', b';', false), (8, 1));
+    }
+
+    #[test]
+    fn test_convert_csv_parallel_matches_sequential() -> anyhow::Result<()> {
+        let mut input = std::fs::read_to_string("assets/juventus.csv")?;
+        input.push_str("\"Line\nBreak\",\"Quote \"\"q\"\"\",,,\r\n\r\n,,,Italy,99");
+        for args in [
+            &["--format", "json"][..],
+            &["--format", "csv", "--infer"],
+            &["--format", "ndjson", "--infer", "--no-header", "--flexible"],
+            &["--format", "ndjson", "--where", "Nationality == 'Italy'", "--limit", "4"],
+            &["--format", "yaml", "--sort-by", "Kit Number", "--desc"],
+            &["--format", "md", "--no-header", "--flexible"],
+        ] {
+            let expected = convert(&input, args, CHUNK_SIZE)?;
+            for chunk_size in [1, 7, 64, 500] {
+                let jobs = ["--jobs", "3"];
+                let actual = convert(&input, &[args, &jobs].concat(), chunk_size)?;
+                assert_eq!(actual, expected, "{args:?} in chunks of {chunk_size}");
+            }
+        }
+        // A stray quote in an unquoted field must not throw off the chunk boundaries of a later quoted field
+        let input = "h,x\n5\"11,\"a\nb\"\n1,2\n3,4\n";
+        for args in [&["--format", "ndjson"][..], &["--format", "ndjson", "--flexible"]] {
+            let expected = convert(input, args, CHUNK_SIZE)?;
+            for chunk_size in 1..input.len() {
+                assert_eq!(convert(input, &[args, &["--jobs", "2"]].concat(), chunk_size)?, expected, "chunks of {chunk_size}");
+            }
+        }
+        // A text cell in a later chunk still makes the whole column text
+        let args = ["--format", "ndjson", "--infer", "--jobs", "2"];
+        assert_eq!(convert("n\n1\n2\nx\n", &args, 2)?, "{\"n\":\"1\"}\n{\"n\":\"2\"}\n{\"n\":\"x\"}\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_csv_parallel_errors() {
+        let input = "Name,Kit\nBuffon,77\nPerin,37\nSzczesny,abc\n";
+        let err = convert(input, &["--jobs", "2", "--schema", "Kit:int"], 10).unwrap_err();
+        assert_eq!(err.to_string(), "row 3, column 'Kit': 'abc' is not an integer");
+        let err = convert("a,b\n1,2\n3\n", &["--jobs", "2"], 4).unwrap_err();
+        assert_eq!(err.to_string(), "row 2: found 1 fields, but the header has 2");
+        let err = convert("1,2\n3,4\n5\n", &["--jobs", "2", "--no-header"], 4).unwrap_err();
+        assert_eq!(err.to_string(), "row 3: found 1 fields, but the first row has 2");
+        assert_eq!(convert("", &["--jobs", "2"], 4).unwrap(), "[]");
+        assert_eq!(convert("\n\n\na\n1\n", &["--jobs", "2", "--format", "csv"], 1).unwrap(), "a\n1\n");
+
+        let (gbk, _, _) = encoding_rs::GBK.encode("名字,\"城市\"\n布冯,\"都\n灵\"\n");
+        let opts = CsvOpts::parse_from(["csv", "-i", "-", "--jobs", "2", "--encoding", "gbk", "--format", "ndjson"]);
+        let mut output = Vec::new();
+        convert_csv_parallel(&gbk[..], &mut output, &opts, 3).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"名字\":\"布冯\",\"城市\":\"都\\n灵\"}\n");
+    }
+}
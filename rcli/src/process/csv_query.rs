@@ -0,0 +1,451 @@
+use std::{cmp::Ordering, iter::Peekable, str::Chars};
+
+use serde_json::{Map, Value};
+
+use crate::cli::CsvOpts;
+
+use super::csv_convert::cell_text;
+
+/// Row filter parsed from `--where`, e.g. `Position == "Goalkeeper" and (Kit < 10 or Name contains 'Buf')`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(String, CompareOp, Literal),
+    Contains(String, Literal),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Literal),
+    Op(CompareOp),
+    And,
+    Or,
+    Not,
+    Contains,
+    LParen,
+    RParen,
+}
+
+/// Select, filter, sort and limit applied between reading records and writing them
+#[derive(Debug, Default)]
+pub struct Query {
+    pub select: Vec<String>,
+    pub filter: Option<Expr>,
+    pub sort_by: Option<String>,
+    pub desc: bool,
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    pub fn from_opts(opts: &CsvOpts) -> anyhow::Result<Self> {
+        let filter = match &opts.filter {
+            Some(expr) => Some(expr.parse()?),
+            None => None,
+        };
+        Ok(Query {
+            select: opts.select.clone(),
+            filter,
+            sort_by: opts.sort_by.clone(),
+            desc: opts.desc,
+            limit: opts.limit,
+        })
+    }
+
+    /// Make sure every referenced column exists, when the input has a header row
+    pub fn check_columns(&self, headers: &[String]) -> anyhow::Result<()> {
+        if headers.is_empty() {
+            return Ok(());
+        }
+        let mut columns = self.select.iter().chain(self.sort_by.iter()).collect::<Vec<_>>();
+        if let Some(filter) = &self.filter {
+            filter.columns(&mut columns);
+        }
+        match columns.into_iter().find(|column| !headers.contains(column)) {
+            Some(column) => anyhow::bail!("Column '{}' not found in header", column),
+            None => Ok(()),
+        }
+    }
+
+    pub fn matches(&self, row: &Value) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter.eval(row))
+    }
+
+    /// Keep only the selected columns, in the order they were selected
+    pub fn project(&self, row: Value) -> Value {
+        if self.select.is_empty() {
+            return row;
+        }
+        let Value::Object(mut map) = row else {
+            return row;
+        };
+        let projected = self
+            .select
+            .iter()
+            .filter_map(|column| map.remove(column).map(|value| (column.clone(), value)))
+            .collect::<Map<String, Value>>();
+        Value::Object(projected)
+    }
+
+    /// Sorting needs every row, so only then are rows buffered
+    pub fn needs_buffer(&self) -> bool {
+        self.sort_by.is_some()
+    }
+
+    pub fn sort(&self, rows: &mut [Value]) {
+        if let Some(column) = &self.sort_by {
+            rows.sort_by(|a, b| compare_values(a.get(column), b.get(column), self.desc));
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, row: &Value) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(row) || b.eval(row),
+            Expr::And(a, b) => a.eval(row) && b.eval(row),
+            Expr::Not(a) => !a.eval(row),
+            Expr::Compare(column, op, literal) => {
+                let ordering = compare_literal(row.get(column), literal);
+                match op {
+                    CompareOp::Eq => ordering == Some(Ordering::Equal),
+                    CompareOp::Ne => ordering != Some(Ordering::Equal),
+                    CompareOp::Lt => ordering == Some(Ordering::Less),
+                    CompareOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    CompareOp::Gt => ordering == Some(Ordering::Greater),
+                    CompareOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                }
+            },
+            Expr::Contains(column, literal) => {
+                let needle = match literal {
+                    Literal::String(s) => s.clone(),
+                    Literal::Number(n) => n.to_string(),
+                    Literal::Bool(b) => b.to_string(),
+                    Literal::Null => return false,
+                };
+                row.get(column).is_some_and(|value| cell_text(value).contains(&needle))
+            },
+        }
+    }
+
+    fn columns<'a>(&'a self, columns: &mut Vec<&'a String>) {
+        match self {
+            Expr::Or(a, b) | Expr::And(a, b) => {
+                a.columns(columns);
+                b.columns(columns);
+            },
+            Expr::Not(a) => a.columns(columns),
+            Expr::Compare(column, _, _) | Expr::Contains(column, _) => columns.push(column),
+        }
+    }
+}
+
+/// Numbers (and numeric strings) compare numerically, everything else as text
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok().filter(|f: &f64| f.is_finite()),
+        _ => None,
+    }
+}
+
+fn is_null(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) => true,
+        Some(Value::String(s)) => s.is_empty(),
+        _ => false,
+    }
+}
+
+fn compare_literal(value: Option<&Value>, literal: &Literal) -> Option<Ordering> {
+    if let Literal::Null = literal {
+        return is_null(value).then_some(Ordering::Equal);
+    }
+    let value = value.filter(|v| !v.is_null())?;
+    match literal {
+        Literal::Number(n) => as_number(value)?.partial_cmp(n),
+        Literal::Bool(b) => match value {
+            Value::Bool(v) => Some(v.cmp(b)),
+            _ => Some(cell_text(value).to_ascii_lowercase().as_str().cmp(if *b { "true" } else { "false" })),
+        },
+        Literal::String(s) => Some(cell_text(value).as_ref().cmp(s.as_str())),
+        Literal::Null => None,
+    }
+}
+
+/// Ordering for `--sort-by`: numbers numerically, then the rest as text, then nulls in both directions.
+/// Keeping numbers and text apart makes it a total order, comparing "9", "10" and "1a" pairwise would not be
+fn compare_values(a: Option<&Value>, b: Option<&Value>, desc: bool) -> Ordering {
+    match (is_null(a), is_null(b)) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        _ => {},
+    }
+    let (Some(a), Some(b)) = (a, b) else {
+        return Ordering::Equal;
+    };
+    let ordering = match (as_number(a), as_number(b)) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => cell_text(a).cmp(&cell_text(b)),
+    };
+    if desc { ordering.reverse() } else { ordering }
+}
+
+impl std::str::FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or_expr()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => anyhow::bail!("Unexpected {:?} in where clause '{}'", token, s),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            },
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            },
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            },
+            '"' | '\'' => {
+                chars.next();
+                tokens.push(Token::Literal(Literal::String(quoted(&mut chars, c)?)));
+            },
+            // Backticks quote column names with spaces, e.g. `Kit Number`
+            '`' => {
+                chars.next();
+                tokens.push(Token::Ident(quoted(&mut chars, c)?));
+            },
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let eq = chars.next_if_eq(&'=').is_some();
+                let op = match (c, eq) {
+                    ('=', _) => CompareOp::Eq,
+                    ('!', true) => CompareOp::Ne,
+                    ('<', false) => CompareOp::Lt,
+                    ('<', true) => CompareOp::Le,
+                    ('>', false) => CompareOp::Gt,
+                    ('>', true) => CompareOp::Ge,
+                    _ => anyhow::bail!("Expected '!=' in where clause '{}'", s),
+                };
+                tokens.push(Token::Op(op));
+            },
+            c if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+                    word.push(c);
+                }
+                tokens.push(word_token(word));
+            },
+            _ => anyhow::bail!("Unexpected character '{}' in where clause '{}'", c, s),
+        }
+    }
+    Ok(tokens)
+}
+
+fn quoted(chars: &mut Peekable<Chars>, quote: char) -> anyhow::Result<String> {
+    let mut text = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            c if c == quote => return Ok(text),
+            c => text.push(c),
+        }
+    }
+    anyhow::bail!("Unterminated {} in where clause", quote)
+}
+
+fn word_token(word: String) -> Token {
+    match word.to_ascii_lowercase().as_str() {
+        "and" => Token::And,
+        "or" => Token::Or,
+        "not" => Token::Not,
+        "contains" => Token::Contains,
+        "true" => Token::Literal(Literal::Bool(true)),
+        "false" => Token::Literal(Literal::Bool(false)),
+        "null" => Token::Literal(Literal::Null),
+        _ => match word.parse::<f64>() {
+            Ok(n) if word.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => Token::Literal(Literal::Number(n)),
+            _ => Token::Ident(word),
+        },
+    }
+}
+
+/// Recursive descent: or_expr := and_expr ("or" and_expr)*, and_expr := unary ("and" unary)*,
+/// unary := "not" unary | "(" or_expr ")" | column op literal | column "contains" literal
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or_expr(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.and_expr()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        match self.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::LParen) => {
+                let expr = self.or_expr()?;
+                if !self.eat(&Token::RParen) {
+                    anyhow::bail!("Missing ')' in where clause");
+                }
+                Ok(expr)
+            },
+            Some(Token::Ident(column)) => match (self.next(), self.next()) {
+                (Some(Token::Op(op)), Some(Token::Literal(literal))) => Ok(Expr::Compare(column, op, literal)),
+                (Some(Token::Contains), Some(Token::Literal(literal))) => Ok(Expr::Contains(column, literal)),
+                _ => anyhow::bail!("Expected a comparison after column '{}'", column),
+            },
+            Some(token) => anyhow::bail!("Unexpected {:?} in where clause", token),
+            None => anyhow::bail!("Unexpected end of where clause"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn expr(s: &str) -> Expr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_expr() {
+        assert_eq!(
+            expr(r#"Position == "Goalkeeper""#),
+            Expr::Compare("Position".into(), CompareOp::Eq, Literal::String("Goalkeeper".into()))
+        );
+        assert_eq!(
+            expr("`Kit Number` >= 10 and not Name contains 'Buf' or Active = true"),
+            Expr::Or(
+                Box::new(Expr::And(
+                    Box::new(Expr::Compare("Kit Number".into(), CompareOp::Ge, Literal::Number(10.0))),
+                    Box::new(Expr::Not(Box::new(Expr::Contains("Name".into(), Literal::String("Buf".into()))))),
+                )),
+                Box::new(Expr::Compare("Active".into(), CompareOp::Eq, Literal::Bool(true))),
+            )
+        );
+        assert!("Name ==".parse::<Expr>().is_err());
+        assert!("(Name == 'a'".parse::<Expr>().is_err());
+        assert!("Name == 'a' Kit".parse::<Expr>().is_err());
+        assert!("Name ! 'a'".parse::<Expr>().is_err());
+        assert!("Name == 'a".parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn test_eval_expr() {
+        let row = json!({"Name": "Gianluigi Buffon", "Kit Number": "77", "Height": 1.92, "Active": true, "Note": ""});
+        assert!(expr("`Kit Number` > 9").eval(&row));
+        assert!(expr("`Kit Number` < 100 and Height >= 1.9").eval(&row));
+        assert!(!expr("Name == 'Buffon'").eval(&row));
+        assert!(expr("Name contains 'Buffon' and Active == true").eval(&row));
+        assert!(expr("Name != 'Perin' and (Active == false or Note == null)").eval(&row));
+        assert!(expr("Missing == null").eval(&row));
+        assert!(!expr("Name > 10").eval(&row));
+        assert!(expr("Name > 'A'").eval(&row));
+    }
+
+    #[test]
+    fn test_query_sort_project() {
+        let query = Query {
+            select: vec!["Kit".into(), "Name".into()],
+            sort_by: Some("Kit".into()),
+            desc: true,
+            ..Default::default()
+        };
+        let mut rows = vec![
+            json!({"Name": "Perin", "Kit": "37"}),
+            json!({"Name": "Buffon", "Kit": "77"}),
+            json!({"Name": "Pinsoglio", "Kit": ""}),
+            json!({"Name": "Szczesny", "Kit": "1"}),
+        ];
+        query.sort(&mut rows);
+        let names = rows.iter().map(|row| row["Name"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(names, ["Buffon", "Perin", "Szczesny", "Pinsoglio"]);
+        assert_eq!(query.project(rows.remove(0)).to_string(), r#"{"Kit":"77","Name":"Buffon"}"#);
+        assert!(query.check_columns(&["Name".into(), "Kit".into()]).is_ok());
+        assert!(query.check_columns(&["Name".into()]).is_err());
+    }
+
+    #[test]
+    fn test_query_sort_mixed() {
+        let query = Query { sort_by: Some("Kit".into()), ..Default::default() };
+        let kits = ["1a", "10", "", "9", "b", "2.5"];
+        // Every rotation of the input sorts the same way
+        for start in 0..kits.len() {
+            let mut rows = kits.iter().cycle().skip(start).take(kits.len()).map(|kit| json!({ "Kit": kit })).collect::<Vec<_>>();
+            query.sort(&mut rows);
+            let sorted = rows.iter().map(|row| row["Kit"].as_str().unwrap()).collect::<Vec<_>>();
+            assert_eq!(sorted, ["2.5", "9", "10", "1a", "b", ""]);
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::{fmt, io::Read, path::Path, str::FromStr};
+
+use csv::StringRecord;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::{cli::CellType, get_reader};
+
+use super::{csv_convert::{cell_text, column_positions}, csv_types::parse_value};
+
+/// Typed record layout, loaded from a YAML or JSON schema file:
+///
+/// ```yaml
+/// fields:
+///   - name: kit
+///     column: Kit Number
+///     aliases: [Kit, Number]
+///     type: u8
+///     default: 0
+/// ```
+///
+/// `column` defaults to the field name, the first of `column` and `aliases` found in the header is read.
+/// Empty cells take the default, or are null for fields without one. A column missing from the header
+/// also takes the default, and is an error for fields without one, so a misspelled column is not silently null
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordSchema {
+    pub fields: Vec<FieldSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub name: String,
+    #[serde(default)]
+    pub column: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(rename = "type", default)]
+    pub ty: FieldType,
+    #[serde(default)]
+    pub default: Option<Value>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    #[default]
+    #[serde(alias = "str")]
+    String,
+    #[serde(alias = "boolean")]
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    #[serde(alias = "int", alias = "integer")]
+    I64,
+    F32,
+    #[serde(alias = "float", alias = "number")]
+    F64,
+}
+
+/// A schema resolved against a header, ready to decode records
+#[derive(Debug)]
+pub struct RecordDecoder {
+    fields: Vec<ResolvedField>,
+}
+
+#[derive(Debug)]
+struct ResolvedField {
+    name: String,
+    column: String,
+    position: Option<usize>,
+    ty: FieldType,
+    default: Value,
+}
+
+impl FieldType {
+    /// Convert a non empty cell, rejecting values out of the type's range
+    pub fn parse(self, s: &str) -> Result<Value, String> {
+        fn number<T: FromStr>(s: &str) -> Option<Value>
+        where
+            Value: From<T>,
+        {
+            s.parse::<T>().ok().map(Value::from)
+        }
+
+        let trimmed = s.trim();
+        let value = match self {
+            FieldType::String => Some(Value::String(s.into())),
+            FieldType::Bool => return parse_value(s, CellType::Bool),
+            FieldType::U8 => number::<u8>(trimmed),
+            FieldType::U16 => number::<u16>(trimmed),
+            FieldType::U32 => number::<u32>(trimmed),
+            FieldType::U64 => number::<u64>(trimmed),
+            FieldType::I8 => number::<i8>(trimmed),
+            FieldType::I16 => number::<i16>(trimmed),
+            FieldType::I32 => number::<i32>(trimmed),
+            FieldType::I64 => number::<i64>(trimmed),
+            // Range checked as f32, but kept as written instead of widening the rounded f32
+            FieldType::F32 => trimmed.parse::<f32>().ok().filter(|f| f.is_finite()).and_then(|_| number::<f64>(trimmed)),
+            FieldType::F64 => trimmed.parse::<f64>().ok().filter(|f| f.is_finite()).map(Value::from),
+        };
+        value.ok_or_else(|| {
+            let ty = Into::<&str>::into(self);
+            let article = if ty.starts_with(['i', 'f']) { "an" } else { "a" };
+            format!("'{}' is not {} {}", s, article, ty)
+        })
+    }
+}
+
+impl From<FieldType> for &str {
+    fn from(ty: FieldType) -> Self {
+        match ty {
+            FieldType::String => "string",
+            FieldType::Bool => "bool",
+            FieldType::U8 => "u8",
+            FieldType::U16 => "u16",
+            FieldType::U32 => "u32",
+            FieldType::U64 => "u64",
+            FieldType::I8 => "i8",
+            FieldType::I16 => "i16",
+            FieldType::I32 => "i32",
+            FieldType::I64 => "i64",
+            FieldType::F32 => "f32",
+            FieldType::F64 => "f64",
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Into::<&str>::into(*self))
+    }
+}
+
+impl RecordSchema {
+    /// Parse a schema as JSON when the file ends in .json, and as YAML otherwise
+    pub fn parse(content: &str, path: &str) -> anyhow::Result<Self> {
+        let schema = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(content)?,
+            _ => serde_yaml::from_str(content)?,
+        };
+        Ok(schema)
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let mut content = String::new();
+        get_reader(path)?.read_to_string(&mut content)?;
+        Self::parse(&content, path)
+    }
+
+    /// Find each field's column in the header, named col1..colN when there is no header
+    pub fn decoder(&self, headers: &[String]) -> anyhow::Result<RecordDecoder> {
+        let mut fields = Vec::with_capacity(self.fields.len());
+        for field in &self.fields {
+            let column = field.column.as_ref().unwrap_or(&field.name);
+            let found = std::iter::once(column)
+                .chain(&field.aliases)
+                .find_map(|name| column_positions(headers, std::slice::from_ref(name), "header").ok().map(|p| (name, p[0])));
+            let default = match &field.default {
+                Some(default) => field
+                    .ty
+                    .parse(&cell_text(default))
+                    .map_err(|e| anyhow::anyhow!("Field '{}' default: {}", field.name, e))?,
+                None => Value::Null,
+            };
+            if found.is_none() && default.is_null() {
+                anyhow::bail!("Column '{}' of field '{}' not found in header", column, field.name);
+            }
+            fields.push(ResolvedField {
+                name: field.name.clone(),
+                column: found.map_or(column, |(name, _)| name).clone(),
+                position: found.map(|(_, position)| position),
+                ty: field.ty,
+                default,
+            });
+        }
+        Ok(RecordDecoder { fields })
+    }
+}
+
+impl RecordDecoder {
+    /// Field names, the keys of the decoded objects
+    pub fn names(&self) -> Vec<String> {
+        self.fields.iter().map(|field| field.name.clone()).collect()
+    }
+
+    /// Decode the `row`-th data record (1-based) into an object keyed by field name, in schema order
+    pub fn decode(&self, row: usize, record: &StringRecord) -> anyhow::Result<Value> {
+        let mut map = Map::with_capacity(self.fields.len());
+        for field in &self.fields {
+            let cell = field.position.and_then(|i| record.get(i)).unwrap_or("");
+            let value = if cell.is_empty() && (field.ty != FieldType::String || !field.default.is_null()) {
+                field.default.clone()
+            } else {
+                field
+                    .ty
+                    .parse(cell)
+                    .map_err(|e| anyhow::anyhow!("row {}, column '{}': {}", row, field.column, e))?
+            };
+            map.insert(field.name.clone(), value);
+        }
+        Ok(Value::Object(map))
+    }
+
+    /// Decode straight into a caller's record type, e.g. a `#[derive(Deserialize)]` struct
+    pub fn decode_as<T: DeserializeOwned>(&self, row: usize, record: &StringRecord) -> anyhow::Result<T> {
+        Ok(serde_json::from_value(self.decode(row, record)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Player {
+        name: String,
+        dob: String,
+        kit: u8,
+        captain: bool,
+    }
+
+    fn decoder(schema: &str, headers: &[&str]) -> anyhow::Result<RecordDecoder> {
+        let headers = headers.iter().map(|header| header.to_string()).collect::<Vec<_>>();
+        RecordSchema::parse(schema, "schema.yaml")?.decoder(&headers)
+    }
+
+    #[test]
+    fn test_decode_fixture_schema() -> anyhow::Result<()> {
+        let schema = RecordSchema::load("fixtures/players.schema.yaml")?;
+        let mut reader = csv::Reader::from_path("assets/juventus.csv")?;
+        let headers = reader.headers()?.iter().map(String::from).collect::<Vec<_>>();
+        let decoder = schema.decoder(&headers)?;
+        assert_eq!(decoder.names(), ["name", "position", "dob", "nationality", "kit", "captain"]);
+
+        let record = reader.records().nth(2).unwrap()?;
+        let player: Player = decoder.decode_as(3, &record)?;
+        assert_eq!(
+            player,
+            Player { name: "Gianluigi Buffon".into(), dob: "Jan 28, 1978 (41)".into(), kit: 77, captain: false }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_aliases_and_defaults() -> anyhow::Result<()> {
+        let schema = "
+fields:
+  - { name: name, column: Player, aliases: [Name] }
+  - { name: kit, column: Kit Number, aliases: [Kit], type: u8, default: 0 }
+  - { name: rating, type: f32 }
+  - { name: club, default: Juventus }
+";
+        let decoder = decoder(schema, &["Name", "Kit", "rating"])?;
+        let record = StringRecord::from(vec!["Buffon", "", "8.5"]);
+        assert_eq!(decoder.decode(1, &record)?, json!({"name": "Buffon", "kit": 0, "rating": 8.5, "club": "Juventus"}));
+        let record = StringRecord::from(vec!["Perin", "37", ""]);
+        assert_eq!(decoder.decode(2, &record)?, json!({"name": "Perin", "kit": 37, "rating": null, "club": "Juventus"}));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_errors() -> anyhow::Result<()> {
+        let schema = "fields: [{ name: kit, column: Kit Number, type: u8 }]";
+        let decoder = decoder(schema, &["Kit Number"])?;
+        let error = decoder.decode(17, &StringRecord::from(vec!["abc"])).unwrap_err();
+        assert_eq!(error.to_string(), "row 17, column 'Kit Number': 'abc' is not a u8");
+        let error = decoder.decode(18, &StringRecord::from(vec!["300"])).unwrap_err();
+        assert_eq!(error.to_string(), "row 18, column 'Kit Number': '300' is not a u8");
+
+        let error = self::decoder(schema, &["Name"]).unwrap_err();
+        assert_eq!(error.to_string(), "Column 'Kit Number' of field 'kit' not found in header");
+        // With a default the missing column is fine, every record gets the default
+        let decoder = self::decoder("fields: [{ name: kit, column: Kit Number, type: u8, default: 1 }]", &["Name"])?;
+        assert_eq!(decoder.decode(1, &StringRecord::from(vec!["Buffon"]))?, json!({"kit": 1}));
+        assert!(self::decoder("fields: [{ name: kit, type: u8, default: -1 }]", &[]).is_err());
+        assert!(self::decoder("fields: [{ name: kit, type: date }]", &["kit"]).is_err());
+        assert_eq!(FieldType::I16.parse("x"), Err("'x' is not an i16".into()));
+        assert_eq!(FieldType::F32.parse("1e39"), Err("'1e39' is not an f32".into()));
+        Ok(())
+    }
+}
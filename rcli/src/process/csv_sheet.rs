@@ -0,0 +1,125 @@
+use std::{io::Write, path::Path};
+
+use calamine::{open_workbook_auto, Data, Reader};
+use csv::StringRecord;
+
+use crate::cli::CsvOpts;
+
+use super::csv_convert::{row_writer, RowPipeline, RowSink, INFER_ROWS};
+
+const SPREADSHEET_EXTENSIONS: [&str; 5] = ["xlsx", "xlsm", "xlsb", "xls", "ods"];
+
+/// Whether the input is a workbook rather than CSV text, by its extension
+pub fn is_spreadsheet(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SPREADSHEET_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Text of a cell as it would appear in a CSV export: dates as ISO 8601, whole floats without ".0"
+fn sheet_cell(cell: &Data) -> String {
+    match cell {
+        Data::DateTime(date) if date.is_datetime() => match date.as_datetime() {
+            Some(date) => {
+                let text = date.format("%Y-%m-%d %H:%M:%S").to_string();
+                text.strip_suffix(" 00:00:00").map_or(text.clone(), String::from)
+            },
+            None => cell.to_string(),
+        },
+        other => other.to_string(),
+    }
+}
+
+/// Like `convert_csv`, but reads the rows of a workbook sheet, selected by name or 0-based index.
+/// Cells are turned into their text first, so --infer, --schema and the query options work as on CSV
+pub fn convert_sheet<W: Write>(path: &str, writer: W, opts: &CsvOpts) -> anyhow::Result<()> {
+    let mut workbook = open_workbook_auto(path)?;
+    let names = workbook.sheet_names();
+    let index = match &opts.sheet {
+        None => 0,
+        Some(sheet) => names
+            .iter()
+            .position(|name| name == sheet)
+            .or_else(|| sheet.parse::<usize>().ok().filter(|i| *i < names.len()))
+            .ok_or_else(|| anyhow::anyhow!("Sheet '{}' not found in {}, it has: {}", sheet, path, names.join(", ")))?,
+    };
+    let range = workbook.worksheet_range_at(index).ok_or_else(|| anyhow::anyhow!("{} has no sheets", path))??;
+
+    let mut records = range.rows().map(|cells| cells.iter().map(sheet_cell).collect::<StringRecord>());
+    let headers = match opts.read.header {
+        true => records.next().map(|header| header.iter().map(String::from).collect()).unwrap_or_default(),
+        false => Vec::new(),
+    };
+    let sample = match opts.infer {
+        true => records.by_ref().take(INFER_ROWS).collect(),
+        false => Vec::new(),
+    };
+    let pipeline = RowPipeline::new(opts, headers, &sample)?;
+    let mut sink = RowSink::new(&pipeline, row_writer(opts.format, opts.output_delimiter, writer));
+    for (i, record) in sample.into_iter().chain(records).enumerate() {
+        if !sink.wants_more() {
+            break;
+        }
+        if let Some(value) = pipeline.process(i + 1, &record)? {
+            sink.push(value)?;
+        }
+    }
+    sink.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn convert(args: &[&str]) -> anyhow::Result<String> {
+        let opts = CsvOpts::parse_from(["csv", "-i", "fixtures/players.xlsx", "--format", "ndjson"].iter().chain(args));
+        let mut output = Vec::new();
+        convert_sheet(&opts.input, &mut output, &opts)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    fn rows(output: &str) -> Vec<Value> {
+        output.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_is_spreadsheet() {
+        assert!(is_spreadsheet("fixtures/players.xlsx"));
+        assert!(is_spreadsheet("Report.XLS"));
+        assert!(!is_spreadsheet("fixtures/players.csv"));
+        assert!(!is_spreadsheet("-"));
+    }
+
+    #[test]
+    fn test_convert_sheet() -> anyhow::Result<()> {
+        let expected = "\
+{\"Name\":\"Gianluigi Buffon\",\"Kit Number\":\"77\",\"Joined\":\"2001-07-03\",\"Captain\":\"true\",\"Rating\":\"8.5\"}
+{\"Name\":\"Mattia Perin\",\"Kit Number\":\"37\",\"Joined\":\"2017-07-01\",\"Captain\":\"false\",\"Rating\":\"\"}
+{\"Name\":\"Wojciech Szczesny\",\"Kit Number\":\"1\",\"Joined\":\"2017-07-01\",\"Captain\":\"false\",\"Rating\":\"7.25\"}
+";
+        assert_eq!(convert(&[])?, expected);
+        assert_eq!(convert(&["--sheet", "Players"])?, expected);
+        assert_eq!(convert(&["--sheet", "0"])?, expected);
+
+        let output = convert(&["--infer", "--where", "Captain == false", "--select", "Name,Rating"])?;
+        assert_eq!(rows(&output), [serde_json::json!({"Name": "Mattia Perin", "Rating": null}), serde_json::json!({"Name": "Wojciech Szczesny", "Rating": 7.25})]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_sheet_select() -> anyhow::Result<()> {
+        assert_eq!(convert(&["--sheet", "Clubs"])?, "{\"Club\":\"Juventus\",\"City\":\"Turin\"}\n");
+        assert_eq!(
+            convert(&["--sheet", "1", "--no-header"])?,
+            "{\"col1\":\"Club\",\"col2\":\"City\"}\n{\"col1\":\"Juventus\",\"col2\":\"Turin\"}\n"
+        );
+        let err = convert(&["--sheet", "Teams"]).unwrap_err();
+        assert_eq!(err.to_string(), "Sheet 'Teams' not found in fixtures/players.xlsx, it has: Players, Clubs");
+        assert!(convert(&["--sheet", "2"]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+
+use csv::StringRecord;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::{cli::CsvShowOpts, get_reader};
+
+use super::{csv_convert::{column_name, csv_headers, csv_reader}, csv_types::infer_value};
+
+/// Write an aligned table, sized by display width so CJK text lines up.
+/// Cells wider than `max_width` (when non zero) are truncated with '…', numbers are right aligned.
+pub fn write_table<W: Write>(writer: &mut W, headers: &[String], rows: &[Vec<String>], max_width: usize, styled: bool) -> anyhow::Result<()> {
+    let columns = rows.iter().map(Vec::len).chain([headers.len()]).max().unwrap_or(0);
+    let header = (0..columns)
+        .map(|i| fit(&column_name(headers, i), max_width))
+        .collect::<Vec<_>>();
+    let cells = rows
+        .iter()
+        .map(|row| (0..columns).map(|i| fit(row.get(i).map_or("", String::as_str), max_width)).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let widths = (0..columns)
+        .map(|i| cells.iter().map(|row| row[i].width()).chain([header[i].width()]).max().unwrap_or(0))
+        .collect::<Vec<_>>();
+
+    let line = join_cells(&header, &widths, |_| false);
+    if styled {
+        writeln!(writer, "\x1b[1m{}\x1b[0m", line)?;
+    } else {
+        writeln!(writer, "{}", line)?;
+    }
+    let rule = widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>();
+    writeln!(writer, "{}", rule.join("-+-"))?;
+    for row in &cells {
+        writeln!(writer, "{}", join_cells(row, &widths, |cell| infer_value(cell).is_number()))?;
+    }
+    Ok(())
+}
+
+fn join_cells(cells: &[String], widths: &[usize], right_align: impl Fn(&str) -> bool) -> String {
+    let line = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| {
+            let padding = " ".repeat(width - cell.width());
+            if right_align(cell) { padding + cell } else { cell.clone() + &padding }
+        })
+        .collect::<Vec<_>>()
+        .join(" | ");
+    line.trim_end().to_string()
+}
+
+/// Flatten line breaks and tabs, then truncate to `max_width` display columns
+fn fit(cell: &str, max_width: usize) -> String {
+    let cell = cell.replace(['\r', '\n', '\t'], " ");
+    if max_width == 0 || cell.width() <= max_width {
+        return cell;
+    }
+    let mut fitted = String::new();
+    let mut width = 0;
+    for c in cell.chars() {
+        let w = c.width().unwrap_or(0);
+        // Leave one column for the ellipsis
+        if width + w > max_width - 1 {
+            break;
+        }
+        fitted.push(c);
+        width += w;
+    }
+    fitted.push('…');
+    fitted
+}
+
+/// Render the CSV page by page, `next_page` decides whether to go on after each full page
+pub fn show_csv<R: Read, W: Write>(
+    reader: R,
+    writer: &mut W,
+    opts: &CsvShowOpts,
+    styled: bool,
+    mut next_page: impl FnMut(usize) -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    let mut reader = csv_reader(&opts.read, reader);
+    let headers = csv_headers(&opts.read, &mut reader)?;
+    let page_size = if opts.page_size == 0 { usize::MAX } else { opts.page_size };
+    let mut record = StringRecord::new();
+    // Read one record ahead, so there is no prompt for a page that would be empty
+    let mut has_record = reader.read_record(&mut record)?;
+    let mut row = 0;
+    let mut page = 1;
+    loop {
+        let mut rows = Vec::new();
+        while has_record && rows.len() < page_size {
+            row += 1;
+            let mut cells = record.iter().map(String::from).collect::<Vec<_>>();
+            if opts.row_numbers {
+                cells.insert(0, row.to_string());
+            }
+            rows.push(cells);
+            has_record = reader.read_record(&mut record)?;
+        }
+        // Name the columns of the page before adding "#", so a missing header still counts from col1
+        let offset = usize::from(opts.row_numbers);
+        let columns = rows.iter().map(|row| row.len() - offset).chain([headers.len()]).max().unwrap_or(0);
+        let mut names = (0..columns).map(|i| column_name(&headers, i)).collect::<Vec<_>>();
+        if opts.row_numbers {
+            names.insert(0, "#".into());
+        }
+        write_table(writer, &names, &rows, opts.max_width, styled)?;
+        writer.flush()?;
+        if !has_record || !next_page(page)? {
+            break;
+        }
+        page += 1;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+pub fn process_csv_show(opts: &CsvShowOpts) -> anyhow::Result<()> {
+    let reader = get_reader(&opts.input)?;
+    let stdout = io::stdout();
+    let styled = stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    // Only pause between pages when someone is there to press Enter, and stdin is not the input
+    let interactive = stdout.is_terminal() && io::stdin().is_terminal() && opts.input != "-";
+    let mut writer = stdout.lock();
+    show_csv(reader, &mut writer, opts, styled, |page| {
+        if !interactive {
+            return Ok(true);
+        }
+        eprint!("-- page {} -- Enter for more, q to quit ", page);
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        Ok(!answer.trim().eq_ignore_ascii_case("q"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn show(input: &str, args: &[&str]) -> anyhow::Result<String> {
+        let opts = CsvShowOpts::parse_from(["show", "-i", "-"].iter().chain(args));
+        let mut output = Vec::new();
+        show_csv(input.as_bytes(), &mut output, &opts, false, |_| Ok(true))?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_show_csv() -> anyhow::Result<()> {
+        let input = "Name,Nationality,Kit Number\nWojciech Szczesny,Poland,1\nGianluigi Buffon,Italy,77\n";
+        let expected = "\
+Name              | Nationality | Kit Number
+------------------+-------------+-----------
+Wojciech Szczesny | Poland      |          1
+Gianluigi Buffon  | Italy       |         77
+";
+        assert_eq!(show(input, &[])?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_csv_truncates_wide_cells() -> anyhow::Result<()> {
+        let input = "名字,Note\n布冯,\"goalkeeper\nsince 2001\"\n";
+        let expected = "\
+# | 名字 | Note
+--+------+------------
+1 | 布冯 | goalkeeper…
+";
+        assert_eq!(show(input, &["-n", "--max-width", "11"])?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_csv_pages() -> anyhow::Result<()> {
+        assert_eq!(show("1\n2\n3\n4\n", &["--page-size", "2", "--no-header"])?, "col1\n----\n   1\n   2\n\ncol1\n----\n   3\n   4\n");
+
+        let opts = CsvShowOpts::parse_from(["show", "-i", "-", "--page-size", "2"]);
+        let mut output = Vec::new();
+        // Quitting after the first page
+        show_csv("n\n1\n2\n3\n".as_bytes(), &mut output, &opts, false, |_| Ok(false))?;
+        assert_eq!(String::from_utf8(output)?, "n\n-\n1\n2\n");
+
+        assert_eq!(show("a,b\n", &["--no-header", "-n"])?, "# | col1 | col2\n--+------+-----\n1 | a    | b\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit("Buffon", 0), "Buffon");
+        assert_eq!(fit("Buffon", 6), "Buffon");
+        assert_eq!(fit("Buffon", 4), "Buf…");
+        assert_eq!(fit("布冯布冯", 5), "布冯…");
+        assert_eq!(fit("a\nb", 10), "a b");
+    }
+}
@@ -0,0 +1,234 @@
+use std::{collections::HashMap, io::{self, Read, Write}};
+
+use csv::StringRecord;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{cli::{CellType, CsvReadOpts, CsvStatsOpts}, get_reader};
+
+use super::{
+    csv_convert::{cell_text, column_name, csv_headers, csv_reader, end_output, row_writer},
+    csv_show::write_table,
+    csv_types::{infer_type, infer_value, merge_types},
+};
+
+/// Profile of one column, `count` is the number of non empty cells
+#[derive(Debug, Serialize)]
+pub struct ColumnStats {
+    pub column: String,
+    #[serde(rename = "type")]
+    pub ty: Option<String>,
+    pub count: usize,
+    pub nulls: usize,
+    pub distinct: usize,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    pub mean: Option<f64>,
+    pub longest: Option<String>,
+    pub top: Vec<TopValue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopValue {
+    pub value: String,
+    pub count: usize,
+}
+
+/// Running totals of a column, distinct values are counted exactly
+#[derive(Default)]
+struct ColumnAccumulator {
+    count: usize,
+    nulls: usize,
+    ty: Option<CellType>,
+    frequencies: HashMap<String, usize>,
+    sum: f64,
+    min: Option<Value>,
+    max: Option<Value>,
+    longest: Option<String>,
+}
+
+impl ColumnAccumulator {
+    fn add(&mut self, cell: &str) {
+        let Some(ty) = infer_type(cell) else {
+            self.nulls += 1;
+            return;
+        };
+        self.count += 1;
+        self.ty = Some(self.ty.map_or(ty, |current| merge_types(current, ty)));
+        if matches!(ty, CellType::Int | CellType::Float) {
+            let value = infer_value(cell);
+            let n = value.as_f64().unwrap_or_default();
+            self.sum += n;
+            if self.min.as_ref().and_then(Value::as_f64).is_none_or(|min| n < min) {
+                self.min = Some(value.clone());
+            }
+            if self.max.as_ref().and_then(Value::as_f64).is_none_or(|max| n > max) {
+                self.max = Some(value);
+            }
+        }
+        if self.longest.as_ref().is_none_or(|longest| cell.chars().count() > longest.chars().count()) {
+            self.longest = Some(cell.to_string());
+        }
+        match self.frequencies.get_mut(cell) {
+            Some(n) => *n += 1,
+            None => {
+                self.frequencies.insert(cell.to_string(), 1);
+            },
+        }
+    }
+
+    fn finish(self, column: String, top: usize) -> ColumnStats {
+        let numeric = matches!(self.ty, Some(CellType::Int | CellType::Float));
+        let mut frequent = self.frequencies.iter().collect::<Vec<_>>();
+        // Most frequent first, ties broken by value so the output is stable
+        frequent.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        ColumnStats {
+            column,
+            ty: self.ty.map(|ty| ty.to_string()),
+            count: self.count,
+            nulls: self.nulls,
+            distinct: self.frequencies.len(),
+            min: if numeric { self.min } else { None },
+            max: if numeric { self.max } else { None },
+            mean: (numeric && self.count > 0).then(|| self.sum / self.count as f64),
+            longest: self.longest,
+            top: frequent
+                .into_iter()
+                .take(top)
+                .map(|(value, count)| TopValue { value: value.clone(), count: *count })
+                .collect(),
+        }
+    }
+}
+
+/// Profile every column in a single pass over the records
+pub fn csv_stats<R: Read>(reader: R, opts: &CsvReadOpts, top: usize) -> anyhow::Result<Vec<ColumnStats>> {
+    let mut reader = csv_reader(opts, reader);
+    let headers = csv_headers(opts, &mut reader)?;
+    let mut columns = headers.iter().map(|_| ColumnAccumulator::default()).collect::<Vec<_>>();
+    let mut rows = 0;
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        rows += 1;
+        // Longer rows (--flexible or no header) add columns, which were null in earlier rows
+        while columns.len() < record.len() {
+            columns.push(ColumnAccumulator { nulls: rows - 1, ..Default::default() });
+        }
+        for (i, column) in columns.iter_mut().enumerate() {
+            column.add(record.get(i).unwrap_or(""));
+        }
+    }
+    Ok(columns
+        .into_iter()
+        .enumerate()
+        .map(|(i, column)| column.finish(column_name(&headers, i), top))
+        .collect())
+}
+
+fn write_stats_table<W: Write>(writer: &mut W, stats: &[ColumnStats]) -> anyhow::Result<()> {
+    let headers = ["column", "type", "count", "nulls", "distinct", "min", "max", "mean", "longest", "top"]
+        .map(String::from);
+    let optional = |value: Option<&Value>| value.map(|v| cell_text(v).into_owned()).unwrap_or_default();
+    let rows = stats
+        .iter()
+        .map(|s| {
+            let top = s.top.iter().map(|t| format!("{} ({})", t.value, t.count)).collect::<Vec<_>>();
+            vec![
+                s.column.clone(),
+                s.ty.clone().unwrap_or_default(),
+                s.count.to_string(),
+                s.nulls.to_string(),
+                s.distinct.to_string(),
+                optional(s.min.as_ref()),
+                optional(s.max.as_ref()),
+                s.mean.map(|mean| format!("{:.2}", mean)).unwrap_or_default(),
+                s.longest.clone().unwrap_or_default(),
+                top.join(", "),
+            ]
+        })
+        .collect::<Vec<_>>();
+    write_table(writer, &headers, &rows, 40, false)
+}
+
+pub fn process_csv_stats(opts: &CsvStatsOpts) -> anyhow::Result<()> {
+    let reader = get_reader(&opts.input)?;
+    let stats = csv_stats(reader, &opts.read, opts.top)?;
+    let mut stdout = io::stdout().lock();
+    match opts.format {
+        Some(format) => {
+            {
+                let mut writer = row_writer(format, ',', &mut stdout);
+                for column in &stats {
+                    writer.write_row(&serde_json::to_value(column)?)?;
+                }
+                writer.finish()?;
+            }
+            end_output(&mut stdout, format, "-")?;
+        },
+        None => write_stats_table(&mut stdout, &stats)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use serde_json::json;
+
+    use super::*;
+
+    fn stats(input: &str, args: &[&str]) -> anyhow::Result<Vec<ColumnStats>> {
+        let opts = CsvStatsOpts::parse_from(["stats", "-i", "-"].iter().chain(args));
+        csv_stats(input.as_bytes(), &opts.read, opts.top)
+    }
+
+    #[test]
+    fn test_csv_stats() -> anyhow::Result<()> {
+        let input = std::fs::read_to_string("assets/juventus.csv")?;
+        let stats = stats(&input, &["--top", "2"])?;
+        assert_eq!(stats.len(), 5);
+
+        let nationality = &stats[3];
+        assert_eq!(nationality.column, "Nationality");
+        assert_eq!(nationality.ty.as_deref(), Some("string"));
+        assert_eq!((nationality.count, nationality.nulls, nationality.distinct), (27, 0, 14));
+        assert_eq!(nationality.longest.as_deref(), Some("Bosnia-Herzegovina"));
+        assert_eq!(serde_json::to_value(&nationality.top)?, json!([{"value": "Italy", "count": 8}, {"value": "Brazil", "count": 3}]));
+        assert_eq!(nationality.mean, None);
+
+        let kit = &stats[4];
+        assert_eq!(kit.ty.as_deref(), Some("int"));
+        assert_eq!((kit.min.clone(), kit.max.clone()), (Some(json!(1)), Some(json!(77))));
+        assert_eq!(kit.distinct, 27);
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_stats_nulls_and_types() -> anyhow::Result<()> {
+        let stats = stats("1,x\n2.5\n,\n-1,y,true\n", &["--no-header", "--flexible"])?;
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[0].column, "col1");
+        assert_eq!(stats[0].ty.as_deref(), Some("float"));
+        assert_eq!((stats[0].count, stats[0].nulls), (3, 1));
+        assert_eq!((stats[0].min.clone(), stats[0].max.clone()), (Some(json!(-1)), Some(json!(2.5))));
+        assert_eq!(stats[0].mean, Some(2.5 / 3.0));
+        assert_eq!((stats[1].count, stats[1].nulls), (2, 2));
+        assert_eq!((stats[2].ty.as_deref(), stats[2].count, stats[2].nulls), (Some("bool"), 1, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_stats_table() -> anyhow::Result<()> {
+        let stats = stats("Name,Kit\nBuffon,77\nPerin,37\n", &["--top", "1"])?;
+        let mut output = Vec::new();
+        write_stats_table(&mut output, &stats)?;
+        let expected = "\
+column | type   | count | nulls | distinct | min | max | mean  | longest | top
+-------+--------+-------+-------+----------+-----+-----+-------+---------+-----------
+Name   | string |     2 |     0 |        2 |     |     |       | Buffon  | Buffon (1)
+Kit    | int    |     2 |     0 |        2 |  37 |  77 | 57.00 |      77 | 37 (1)
+";
+        assert_eq!(String::from_utf8(output)?, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use csv::StringRecord;
+use serde_json::Value;
+
+use crate::cli::CellType;
+
+/// Guess the type of a cell: empty is null, then integer, float, bool, and string otherwise.
+/// Dates and numbers with leading zeros (zip codes, ids) are kept as strings.
+pub fn infer_value(s: &str) -> Value {
+    if s.is_empty() {
+        return Value::Null;
+    }
+    if looks_numeric(s) {
+        if let Ok(n) = s.parse::<i64>() {
+            return Value::from(n);
+        }
+        if s.contains(['.', 'e', 'E']) {
+            if let Ok(f) = s.parse::<f64>() {
+                if f.is_finite() {
+                    return Value::from(f);
+                }
+            }
+        }
+    }
+    if s.eq_ignore_ascii_case("true") {
+        return Value::Bool(true);
+    }
+    if s.eq_ignore_ascii_case("false") {
+        return Value::Bool(false);
+    }
+    Value::String(s.to_string())
+}
+
+/// Type of the value `infer_value` would produce, `None` for null
+pub fn infer_type(s: &str) -> Option<CellType> {
+    match infer_value(s) {
+        Value::Null => None,
+        Value::Number(n) if n.is_f64() => Some(CellType::Float),
+        Value::Number(_) => Some(CellType::Int),
+        Value::Bool(_) => Some(CellType::Bool),
+        _ => Some(CellType::String),
+    }
+}
+
+/// The narrowest type holding values of both types: int widens to float, anything else mixed is a string
+pub fn merge_types(a: CellType, b: CellType) -> CellType {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (CellType::Int, CellType::Float) | (CellType::Float, CellType::Int) => CellType::Float,
+        _ => CellType::String,
+    }
+}
+
+/// One type per column from a sample of records, merging the types of its cells as `csv stats` does.
+/// A column with only empty cells in the sample has none
+pub fn infer_column_types(sample: &[StringRecord]) -> Vec<Option<CellType>> {
+    let mut types = Vec::new();
+    for record in sample {
+        if record.len() > types.len() {
+            types.resize(record.len(), None);
+        }
+        for (ty, cell) in types.iter_mut().zip(record) {
+            if let Some(cell_type) = infer_type(cell) {
+                *ty = Some(ty.map_or(cell_type, |current| merge_types(current, cell_type)));
+            }
+        }
+    }
+    types
+}
+
+/// Convert a cell of a column inferred as `ty`, empty cells are null.
+/// Cells that do not fit the type, which can only happen past the sample, are kept as strings
+pub fn column_value(s: &str, ty: Option<CellType>) -> Value {
+    match (ty, infer_value(s)) {
+        (_, Value::Null) => Value::Null,
+        (Some(CellType::Float), Value::Number(n)) => n.as_f64().map_or_else(|| Value::String(s.into()), Value::from),
+        (Some(CellType::Int), value) if value.is_i64() => value,
+        (Some(CellType::Bool), value @ Value::Bool(_)) => value,
+        _ => Value::String(s.into()),
+    }
+}
+
+/// Convert a cell to the given type, empty cells of non string types become null
+pub fn parse_value(s: &str, ty: CellType) -> Result<Value, String> {
+    if s.is_empty() && ty != CellType::String {
+        return Ok(Value::Null);
+    }
+    let trimmed = s.trim();
+    let value = match ty {
+        CellType::String => Some(Value::String(s.to_string())),
+        CellType::Int => trimmed.parse::<i64>().ok().map(Value::from),
+        CellType::Float => trimmed.parse::<f64>().ok().filter(|f| f.is_finite()).map(Value::from),
+        CellType::Bool => parse_bool(trimmed).map(Value::Bool),
+    };
+    value.ok_or_else(|| format!("'{}' is not {}", s, type_name(ty)))
+}
+
+/// Type name with its article, for error messages
+pub fn type_name(ty: CellType) -> &'static str {
+    match ty {
+        CellType::String => "a string",
+        CellType::Int => "an integer",
+        CellType::Float => "a float",
+        CellType::Bool => "a bool",
+    }
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" => Some(true),
+        "false" | "no" | "n" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Plain decimal number without a leading zero such as "007"
+fn looks_numeric(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let bytes = digits.as_bytes();
+    match bytes {
+        [] => false,
+        [b'0', b'0'..=b'9', ..] => false,
+        [first, ..] => first.is_ascii_digit(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_infer_value() {
+        assert_eq!(infer_value(""), Value::Null);
+        assert_eq!(infer_value("77"), json!(77));
+        assert_eq!(infer_value("-3"), json!(-3));
+        assert_eq!(infer_value("0"), json!(0));
+        assert_eq!(infer_value("1.5"), json!(1.5));
+        assert_eq!(infer_value("0.5"), json!(0.5));
+        assert_eq!(infer_value("1e3"), json!(1000.0));
+        assert_eq!(infer_value("TRUE"), json!(true));
+        assert_eq!(infer_value("false"), json!(false));
+        assert_eq!(infer_value("007"), json!("007"));
+        assert_eq!(infer_value("1990-04-18"), json!("1990-04-18"));
+        assert_eq!(infer_value("NaN"), json!("NaN"));
+        assert_eq!(infer_value("inf"), json!("inf"));
+        assert_eq!(infer_value("99999999999999999999"), json!("99999999999999999999"));
+        assert_eq!(infer_value("Buffon"), json!("Buffon"));
+    }
+
+    #[test]
+    fn test_infer_type() {
+        assert_eq!(infer_type(""), None);
+        assert_eq!(infer_type("1"), Some(CellType::Int));
+        assert_eq!(infer_type("1.0"), Some(CellType::Float));
+        assert_eq!(infer_type("true"), Some(CellType::Bool));
+        assert_eq!(infer_type("2019-01-01"), Some(CellType::String));
+        assert_eq!(merge_types(CellType::Int, CellType::Float), CellType::Float);
+        assert_eq!(merge_types(CellType::Bool, CellType::Bool), CellType::Bool);
+        assert_eq!(merge_types(CellType::Bool, CellType::Int), CellType::String);
+    }
+
+    #[test]
+    fn test_infer_column_types() {
+        let sample = [
+            StringRecord::from(vec!["1", "007", "", "true"]),
+            StringRecord::from(vec!["2.5", "10121", "", "false", "x"]),
+        ];
+        let types = infer_column_types(&sample);
+        assert_eq!(types, [Some(CellType::Float), Some(CellType::String), None, Some(CellType::Bool), Some(CellType::String)]);
+        assert_eq!(column_value("1", types[0]), json!(1.0));
+        assert_eq!(column_value("10121", types[1]), json!("10121"));
+        assert_eq!(column_value("", types[1]), Value::Null);
+        assert_eq!(column_value("3", types[2]), json!("3"));
+        assert_eq!(column_value("FALSE", types[3]), json!(false));
+        // Past the sample a cell may not fit its column
+        assert_eq!(column_value("1.5", Some(CellType::Int)), json!("1.5"));
+        assert_eq!(column_value("2", Some(CellType::Int)), json!(2));
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_value("007", CellType::Int), Ok(json!(7)));
+        assert_eq!(parse_value(" 1.5 ", CellType::Float), Ok(json!(1.5)));
+        assert_eq!(parse_value("yes", CellType::Bool), Ok(json!(true)));
+        assert_eq!(parse_value("1", CellType::String), Ok(json!("1")));
+        assert_eq!(parse_value("", CellType::Int), Ok(Value::Null));
+        assert_eq!(parse_value("", CellType::String), Ok(json!("")));
+        assert_eq!(parse_value("abc", CellType::Int), Err("'abc' is not an integer".into()));
+        assert_eq!(parse_value("NaN", CellType::Float), Err("'NaN' is not a float".into()));
+        assert_eq!(parse_value("maybe", CellType::Bool), Err("'maybe' is not a bool".into()));
+    }
+}
@@ -0,0 +1,315 @@
+use std::{collections::HashMap, fmt, io::Read, path::Path};
+
+use csv::StringRecord;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{cli::{CellType, CsvReadOpts, CsvValidateOpts}, get_reader};
+
+use super::{csv_convert::{cell_text, column_name, csv_headers, csv_reader}, csv_types::parse_value};
+
+/// The contract a CSV file must satisfy, loosely modelled on JSON Schema:
+///
+/// ```yaml
+/// required: [Name, Kit Number]
+/// columns:
+///   Name: { not_null: true, unique: true }
+///   Kit Number: { type: int, unique: true }
+///   Position: { enum: [Goalkeeper, Centre-Back] }
+///   DOB: { pattern: '^[A-Z][a-z]{2} \d{1,2}, \d{4}' }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationSpec {
+    /// Columns that must be present, named col1..colN when the input has no header
+    pub required: Vec<String>,
+    pub columns: HashMap<String, ColumnSpec>,
+}
+
+/// Constraints on the cells of one column, empty cells only fail `not_null`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnSpec {
+    #[serde(rename = "type")]
+    pub ty: Option<String>,
+    /// Regular expression searched in the cell, anchor it with ^ and $ to match the whole cell
+    pub pattern: Option<String>,
+    #[serde(rename = "enum")]
+    pub allowed: Option<Vec<Value>>,
+    pub unique: bool,
+    pub not_null: bool,
+}
+
+/// A failed constraint, `row` is the 1-based data row and `None` for the header
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub row: Option<usize>,
+    pub column: usize,
+    pub name: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.row {
+            Some(row) => write!(f, "row {}, column {} '{}': {}", row, self.column, self.name, self.message),
+            None => write!(f, "header, column '{}': {}", self.name, self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ValidationReport {
+    pub rows: usize,
+    pub violations: Vec<Violation>,
+}
+
+/// A column spec with its pattern compiled and the values seen so far for `unique`
+struct ColumnRule {
+    ty: Option<CellType>,
+    pattern: Option<Regex>,
+    allowed: Option<Vec<String>>,
+    not_null: bool,
+    seen: Option<HashMap<String, usize>>,
+}
+
+impl ColumnRule {
+    fn new(name: &str, spec: &ColumnSpec) -> anyhow::Result<Self> {
+        let context = |e: anyhow::Error| anyhow::anyhow!("Spec column '{}': {}", name, e);
+        let ty = spec.ty.as_deref().map(str::parse).transpose().map_err(context)?;
+        let pattern = spec
+            .pattern
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| context(e.into()))?;
+        Ok(ColumnRule {
+            ty,
+            pattern,
+            allowed: spec.allowed.as_ref().map(|values| values.iter().map(|v| cell_text(v).into_owned()).collect()),
+            not_null: spec.not_null,
+            seen: spec.unique.then(HashMap::new),
+        })
+    }
+
+    /// Check one cell, returning the first failed constraint
+    fn check(&mut self, row: usize, cell: &str) -> Option<String> {
+        if cell.is_empty() {
+            return self.not_null.then(|| "value is required".to_string());
+        }
+        if let Some(ty) = self.ty {
+            if let Err(e) = parse_value(cell, ty) {
+                return Some(e);
+            }
+        }
+        if let Some(pattern) = &self.pattern {
+            if !pattern.is_match(cell) {
+                return Some(format!("'{}' does not match pattern '{}'", cell, pattern));
+            }
+        }
+        if let Some(allowed) = &self.allowed {
+            if !allowed.iter().any(|value| value == cell) {
+                return Some(format!("'{}' is not one of {}", cell, allowed.join(", ")));
+            }
+        }
+        if let Some(seen) = &mut self.seen {
+            if let Some(first) = seen.get(cell) {
+                return Some(format!("'{}' duplicates row {}", cell, first));
+            }
+            seen.insert(cell.to_string(), row);
+        }
+        None
+    }
+}
+
+/// Parse a spec as JSON when the file ends in .json, and as YAML otherwise
+pub fn parse_spec(content: &str, path: &str) -> anyhow::Result<ValidationSpec> {
+    let spec = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(content)?,
+        _ => serde_yaml::from_str(content)?,
+    };
+    Ok(spec)
+}
+
+/// Position of the column `name`, which without a header has to be one of col1..colN
+fn column_index(headers: &[String], name: &str) -> Option<usize> {
+    if !headers.is_empty() {
+        return headers.iter().position(|header| header == name);
+    }
+    name.strip_prefix("col").and_then(|n| n.parse::<usize>().ok()).and_then(|n| n.checked_sub(1))
+}
+
+/// Check every record against the spec, collecting all violations in one pass
+pub fn validate_csv<R: Read>(reader: R, opts: &CsvReadOpts, spec: &ValidationSpec) -> anyhow::Result<ValidationReport> {
+    let mut reader = csv_reader(opts, reader);
+    let headers = csv_headers(opts, &mut reader)?;
+    let mut rules = HashMap::with_capacity(spec.columns.len());
+    for (name, column) in &spec.columns {
+        rules.insert(name.as_str(), ColumnRule::new(name, column)?);
+    }
+
+    let mut violations = Vec::new();
+    for name in spec.required.iter().filter(|name| column_index(&headers, name).is_none()) {
+        violations.push(Violation {
+            row: None,
+            column: 0,
+            name: name.clone(),
+            message: "required column is missing".into(),
+        });
+    }
+    // A spec column the input does not have would never be checked, most likely a typo
+    let mut unknown = spec
+        .columns
+        .keys()
+        .filter(|name| !spec.required.contains(name) && column_index(&headers, name).is_none())
+        .collect::<Vec<_>>();
+    unknown.sort();
+    for name in unknown {
+        violations.push(Violation {
+            row: None,
+            column: 0,
+            name: name.clone(),
+            message: "spec column is not in the input".into(),
+        });
+    }
+
+    let mut rows = 0;
+    let mut record = StringRecord::new();
+    while reader.read_record(&mut record)? {
+        rows += 1;
+        for (i, cell) in record.iter().enumerate() {
+            let name = column_name(&headers, i);
+            if let Some(message) = rules.get_mut(name.as_str()).and_then(|rule| rule.check(rows, cell)) {
+                violations.push(Violation { row: Some(rows), column: i + 1, name, message });
+            }
+        }
+        // Short rows, allowed by --flexible or missing a header, can lack required columns
+        for name in &spec.required {
+            if let Some(i) = column_index(&headers, name).filter(|i| *i >= record.len()) {
+                violations.push(Violation {
+                    row: Some(rows),
+                    column: i + 1,
+                    name: name.clone(),
+                    message: "required column is missing".into(),
+                });
+            }
+        }
+    }
+    Ok(ValidationReport { rows, violations })
+}
+
+pub fn process_csv_validate(opts: &CsvValidateOpts) -> anyhow::Result<()> {
+    if opts.input == "-" && opts.spec == "-" {
+        anyhow::bail!("Input and spec cannot both be read from stdin");
+    }
+    let mut content = String::new();
+    get_reader(&opts.spec)?.read_to_string(&mut content)?;
+    let spec = parse_spec(&content, &opts.spec)?;
+
+    let report = validate_csv(get_reader(&opts.input)?, &opts.read, &spec)?;
+    let limit = if opts.max_errors == 0 { usize::MAX } else { opts.max_errors };
+    for violation in report.violations.iter().take(limit) {
+        println!("{}", violation);
+    }
+    if report.violations.len() > limit {
+        println!("... and {} more", report.violations.len() - limit);
+    }
+    match report.violations.len() {
+        0 => {
+            eprintln!("{} rows valid", report.rows);
+            Ok(())
+        },
+        n => anyhow::bail!("{} violations in {} rows", n, report.rows),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn validate(input: &str, spec: &str, args: &[&str]) -> anyhow::Result<Vec<String>> {
+        let opts = CsvValidateOpts::parse_from(["validate", "-i", "-", "--spec", "-"].iter().chain(args));
+        let spec = parse_spec(spec, "spec.yaml")?;
+        let report = validate_csv(input.as_bytes(), &opts.read, &spec)?;
+        Ok(report.violations.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn test_validate_fixture_spec() -> anyhow::Result<()> {
+        let spec = parse_spec(&std::fs::read_to_string("fixtures/players.spec.yaml")?, "players.spec.yaml")?;
+        let opts = CsvValidateOpts::parse_from(["validate", "-i", "-", "--spec", "-"]).read;
+        let report = validate_csv(std::fs::File::open("assets/juventus.csv")?, &opts, &spec)?;
+        assert_eq!(report.rows, 27);
+        assert_eq!(report.violations, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_violations() -> anyhow::Result<()> {
+        let spec = r#"
+required: [Name, Kit, Club]
+columns:
+  Name: { not_null: true, unique: true }
+  Kit: { type: int }
+  Position: { enum: [Goalkeeper, Defender] }
+  DOB: { pattern: '^\d{4}-\d{2}-\d{2}$' }
+"#;
+        let input = "Name,Position,DOB,Kit\nBuffon,Goalkeeper,1978-01-28,77\n,Striker,28/01/1978,abc\nBuffon,Defender,,\n";
+        assert_eq!(
+            validate(input, spec, &[])?,
+            vec![
+                "header, column 'Club': required column is missing",
+                "row 2, column 1 'Name': value is required",
+                "row 2, column 2 'Position': 'Striker' is not one of Goalkeeper, Defender",
+                "row 2, column 3 'DOB': '28/01/1978' does not match pattern '^\\d{4}-\\d{2}-\\d{2}$'",
+                "row 2, column 4 'Kit': 'abc' is not an integer",
+                "row 3, column 1 'Name': 'Buffon' duplicates row 1",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_unknown_columns() -> anyhow::Result<()> {
+        let spec = "required: [Club]\ncolumns:\n  Kitt: { type: int }\n  Club: { not_null: true }\n  Name: { not_null: true }\n";
+        assert_eq!(
+            validate("Name,Kit\nBuffon,1\n", spec, &[])?,
+            vec!["header, column 'Club': required column is missing", "header, column 'Kitt': spec column is not in the input"]
+        );
+        let spec = "columns:\n  col2: { type: int }\n  Kit: { type: int }\n";
+        assert_eq!(validate("1,2\n", spec, &["--no-header"])?, vec!["header, column 'Kit': spec column is not in the input"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_without_header() -> anyhow::Result<()> {
+        let spec = "required: [col2]\ncolumns:\n  col1: { type: bool }\n";
+        let violations = validate("yes,1\nmaybe\n", spec, &["--no-header", "--flexible"])?;
+        assert_eq!(
+            violations,
+            vec!["row 2, column 1 'col1': 'maybe' is not a bool", "row 2, column 2 'col2': required column is missing"]
+        );
+        // Without a header a required column can only be found by its colN name
+        let spec = "required: [Name, col0, col1]\n";
+        assert_eq!(
+            validate("a\n", spec, &["--no-header"])?,
+            vec!["header, column 'Name': required column is missing", "header, column 'col0': required column is missing"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_spec_errors() {
+        assert!(parse_spec("columns: { Name: { kind: int } }", "spec.yaml").is_err());
+        assert!(parse_spec(r#"{"columns": {"Kit": {"type": "int"}}}"#, "spec.json").is_ok());
+
+        let opts = CsvValidateOpts::parse_from(["validate", "-i", "-", "--spec", "-"]).read;
+        let spec = parse_spec("columns: { Kit: { type: date } }", "spec.yaml").unwrap();
+        assert!(validate_csv("Kit\n1\n".as_bytes(), &opts, &spec).is_err());
+        let spec = parse_spec("columns: { Kit: { pattern: '(' } }", "spec.yaml").unwrap();
+        assert!(validate_csv("Kit\n1\n".as_bytes(), &opts, &spec).is_err());
+    }
+}
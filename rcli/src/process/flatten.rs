@@ -0,0 +1,128 @@
+use serde_json::{Map, Value};
+
+/// Flatten nested objects and arrays into dotted keys, e.g. `address.city` and `tags.0`
+pub fn flatten_object(object: &Map<String, Value>) -> Vec<(String, Value)> {
+    let mut fields = Vec::with_capacity(object.len());
+    for (key, value) in object {
+        flatten_into(key.clone(), value, &mut fields);
+    }
+    fields
+}
+
+fn flatten_into(prefix: String, value: &Value, fields: &mut Vec<(String, Value)>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                flatten_into(format!("{}.{}", prefix, key), value, fields);
+            }
+        },
+        Value::Array(items) if !items.is_empty() => {
+            for (i, value) in items.iter().enumerate() {
+                flatten_into(format!("{}.{}", prefix, i), value, fields);
+            }
+        },
+        // Scalars and empty containers stay as they are
+        _ => fields.push((prefix, value.clone())),
+    }
+}
+
+/// Rebuild nested objects from dotted keys, the reverse of `flatten_object`.
+/// Objects whose keys are exactly 0..n become arrays, so `tags.0` and `tags.1` give `tags: [..]`
+pub fn unflatten_object(object: Map<String, Value>) -> anyhow::Result<Value> {
+    let mut root = Map::new();
+    for (key, value) in object {
+        let mut parts = key.split('.').peekable();
+        let mut map = &mut root;
+        while let Some(part) = parts.next() {
+            if parts.peek().is_none() {
+                if map.contains_key(part) {
+                    anyhow::bail!("Column '{}' conflicts with a nested column", key);
+                }
+                map.insert(part.to_string(), value);
+                break;
+            }
+            let entry = map.entry(part).or_insert_with(|| Value::Object(Map::new()));
+            map = match entry {
+                // Only objects created here from dotted keys are nested into
+                Value::Object(nested) => nested,
+                _ => anyhow::bail!("Column '{}' conflicts with column '{}'", key, part),
+            };
+        }
+    }
+    Ok(into_arrays(Value::Object(root)))
+}
+
+fn into_arrays(value: Value) -> Value {
+    let Value::Object(map) = value else {
+        return value;
+    };
+    let map = map.into_iter().map(|(key, value)| (key, into_arrays(value))).collect::<Map<_, _>>();
+    let is_array = !map.is_empty() && map.keys().enumerate().all(|(i, key)| key.parse::<usize>() == Ok(i));
+    if is_array {
+        Value::Array(map.into_iter().map(|(_, value)| value).collect())
+    } else {
+        Value::Object(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_flatten_object() {
+        let value = json!({
+            "name": "Buffon",
+            "address": {"city": "Turin", "geo": {"lat": 45.07}},
+            "tags": ["keeper", {"since": 2001}],
+            "empty": {},
+        });
+        let fields = flatten_object(value.as_object().unwrap());
+        assert_eq!(
+            fields,
+            vec![
+                ("name".into(), json!("Buffon")),
+                ("address.city".into(), json!("Turin")),
+                ("address.geo.lat".into(), json!(45.07)),
+                ("tags.0".into(), json!("keeper")),
+                ("tags.1.since".into(), json!(2001)),
+                ("empty".into(), json!({})),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unflatten_object() -> anyhow::Result<()> {
+        let value = json!({
+            "name": "Buffon",
+            "address.city": "Turin",
+            "address.geo.lat": 45.07,
+            "tags.0": "keeper",
+            "tags.1.since": 2001,
+            "kits.1": 77,
+            "kits.3": 1,
+        });
+        let Value::Object(object) = value else { unreachable!() };
+        assert_eq!(
+            unflatten_object(object.clone())?,
+            json!({
+                "name": "Buffon",
+                "address": {"city": "Turin", "geo": {"lat": 45.07}},
+                "tags": ["keeper", {"since": 2001}],
+                // Not a 0..n run of indices, so it stays an object
+                "kits": {"1": 77, "3": 1},
+            })
+        );
+        // Round trip through flatten_object
+        let Value::Object(nested) = unflatten_object(object.clone())? else { unreachable!() };
+        assert_eq!(flatten_object(&nested), object.into_iter().collect::<Vec<_>>());
+
+        let Value::Object(conflict) = json!({"address": "Turin", "address.city": "Turin"}) else { unreachable!() };
+        assert!(unflatten_object(conflict).is_err());
+        let Value::Object(conflict) = json!({"address.city": "Turin", "address": "Turin"}) else { unreachable!() };
+        assert!(unflatten_object(conflict).is_err());
+        Ok(())
+    }
+}
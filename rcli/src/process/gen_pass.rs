@@ -1,53 +1,564 @@
-use rand::{seq::SliceRandom, thread_rng};
+use std::io::Write;
 
-const UPPER: &[u8] = b"ABCDEFGHJKLMNOPQRSTUVWXYZ";
-const LOWER: &[u8] = b"abcdefghijkmnpqrstuvwxyz";
-const NUMBER: &[u8] = b"123456789";
-const SYMBOL: &[u8] = b"!@#$%^&*-_";
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde::Serialize;
 
+use crate::cli::GenpassOpts;
+
+use super::{
+    csv_convert::{end_output, row_writer},
+    passphrase::{process_passphrase, PassphrasePolicy},
+    pattern::{process_pattern, PatternPolicy},
+    strength::{estimate_strength, Strength},
+};
+
+const UPPER: &str = "ABCDEFGHJKLMNOPQRSTUVWXYZ";
+const LOWER: &str = "abcdefghijkmnpqrstuvwxyz";
+const NUMBER: &str = "123456789";
+pub(super) const SYMBOL: &str = "!@#$%^&*-_";
+
+/// Tries at placing the characters before --no-repeat and --no-sequential count as unsatisfiable
+const ATTEMPTS: usize = 100;
+
+/// Longest password generated, enough for secrets and keys of any size
+pub const MAX_LENGTH: u16 = 4096;
+
+/// Passwords generated before --min-score counts as out of reach
+const STRENGTH_ATTEMPTS: usize = 100;
+
+/// log10 of the guesses zxcvbn needs to exceed for each score
+const SCORE_GUESSES_LOG10: [f64; 5] = [0.0, 3.0, 6.0, 8.0, 10.0];
+
+/// What a generated password has to look like
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    pub length: u16,
+    pub upper: bool,
+    pub lower: bool,
+    pub number: bool,
+    pub symbol: bool,
+    /// Characters never used, whatever the sets say
+    pub exclude: String,
+    /// Replaces the default symbols
+    pub symbols: Option<String>,
+    /// Replaces the character classes, the minimum counts then pick from its letters, digits and symbols
+    pub charset: Option<String>,
+    pub min_upper: usize,
+    pub min_digits: usize,
+    pub min_symbols: usize,
+    pub no_repeat: bool,
+    pub no_sequential: bool,
+}
+
+/// Flag setting a class minimum, the class name, the minimum and the test for members of the class
+type ClassMinimum = (&'static str, &'static str, usize, fn(char) -> bool);
+
+/// Characters of one class and how many of them the password needs
+struct CharClass {
+    chars: Vec<char>,
+    min: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            length: 16,
+            upper: true,
+            lower: true,
+            number: true,
+            symbol: true,
+            exclude: String::new(),
+            symbols: None,
+            charset: None,
+            min_upper: 0,
+            min_digits: 0,
+            min_symbols: 0,
+            no_repeat: false,
+            no_sequential: false,
+        }
+    }
+}
+
+impl From<&GenpassOpts> for PasswordPolicy {
+    fn from(opts: &GenpassOpts) -> Self {
+        PasswordPolicy {
+            length: opts.length,
+            upper: opts.uppercase,
+            lower: opts.lowercase,
+            number: opts.number,
+            symbol: opts.symbol,
+            exclude: opts.exclude.clone(),
+            symbols: opts.symbols.clone(),
+            charset: opts.charset.clone(),
+            min_upper: opts.min_upper,
+            min_digits: opts.min_digits,
+            min_symbols: opts.min_symbols,
+            no_repeat: opts.no_repeat,
+            no_sequential: opts.no_sequential,
+        }
+    }
+}
+
+fn is_upper(c: char) -> bool {
+    c.is_uppercase()
+}
+
+fn is_digit(c: char) -> bool {
+    c.is_ascii_digit()
+}
+
+fn is_symbol(c: char) -> bool {
+    !c.is_alphanumeric()
+}
+
+/// Neighbours in the alphabet or among the digits, in either direction
+fn is_sequential(a: char, b: char) -> bool {
+    let kinds: [fn(&char) -> bool; 3] = [char::is_ascii_uppercase, char::is_ascii_lowercase, char::is_ascii_digit];
+    let same_kind = kinds.iter().any(|is| is(&a) && is(&b));
+    same_kind && (a as u32).abs_diff(b as u32) == 1
+}
+
+impl PasswordPolicy {
+    /// Characters of `set` left after the exclusions, each once
+    fn allowed(&self, set: &str) -> Vec<char> {
+        let mut chars = Vec::new();
+        for c in set.chars().filter(|c| !self.exclude.contains(*c)) {
+            if !chars.contains(&c) {
+                chars.push(c);
+            }
+        }
+        chars
+    }
+
+    /// The whole alphabet and the classes the password needs characters of
+    fn classes(&self) -> anyhow::Result<(Vec<char>, Vec<CharClass>)> {
+        let minimums: [ClassMinimum; 3] = [
+            ("--min-upper", "uppercase letters", self.min_upper, is_upper),
+            ("--min-digits", "digits", self.min_digits, is_digit),
+            ("--min-symbols", "symbols", self.min_symbols, is_symbol),
+        ];
+        if let Some(charset) = &self.charset {
+            let alphabet = self.allowed(charset);
+            if alphabet.is_empty() {
+                anyhow::bail!("--charset has no characters left after --exclude");
+            }
+            let mut classes = Vec::new();
+            for (flag, name, min, is) in minimums.into_iter().filter(|(_, _, min, _)| *min > 0) {
+                let chars = alphabet.iter().copied().filter(|c| is(*c)).collect::<Vec<_>>();
+                if chars.is_empty() {
+                    anyhow::bail!("{} {} needs {} in --charset", flag, min, name);
+                }
+                classes.push(CharClass { chars, min });
+            }
+            return Ok((alphabet, classes));
+        }
+
+        let symbols = self.symbols.as_deref().unwrap_or(SYMBOL);
+        if let Some(c) = symbols.chars().find(|c| !is_symbol(*c)) {
+            anyhow::bail!("--symbols must not contain letters or digits, found '{}'", c);
+        }
+        let [upper, digits, symbol] = minimums;
+        let wanted = [
+            (self.upper, UPPER, upper),
+            (self.lower, LOWER, ("", "lowercase letters", 0, char::is_lowercase as fn(char) -> bool)),
+            (self.number, NUMBER, digits),
+            (self.symbol, symbols, symbol),
+        ];
+        let mut alphabet = Vec::new();
+        let mut classes = Vec::new();
+        for (enabled, set, (flag, name, min, _)) in wanted {
+            if !enabled {
+                if min > 0 {
+                    anyhow::bail!("{} {} needs {} enabled", flag, min, name);
+                }
+                continue;
+            }
+            let chars = self.allowed(set);
+            if chars.is_empty() {
+                anyhow::bail!("--exclude leaves no {}", name);
+            }
+            alphabet.extend(&chars);
+            // Every enabled class shows up at least once
+            classes.push(CharClass { chars, min: min.max(1) });
+        }
+        if alphabet.is_empty() {
+            anyhow::bail!("Every character class is disabled, keep at least one or pass --charset");
+        }
+        Ok((alphabet, classes))
+    }
+
+    /// Bits of entropy of a password drawn uniformly from the alphabet.
+    /// An upper bound, required classes and the --no-repeat and --no-sequential rules only take guesses away
+    pub fn entropy(&self) -> anyhow::Result<f64> {
+        let (alphabet, _) = self.classes()?;
+        Ok(self.length as f64 * (alphabet.len() as f64).log2())
+    }
+
+    /// Whether `c` may follow `prev`
+    fn allows(&self, prev: Option<char>, c: char) -> bool {
+        match prev {
+            Some(prev) => !(self.no_repeat && prev == c || self.no_sequential && is_sequential(prev, c)),
+            None => true,
+        }
+    }
+}
+
+/// Pick a character for every slot from left to right, each one allowed after the previous one
+fn fill(slots: &[&[char]], policy: &PasswordPolicy, rng: &mut impl Rng) -> Option<String> {
+    let mut password = String::with_capacity(slots.len());
+    let mut prev = None;
+    for pool in slots {
+        let allowed = pool.iter().filter(|c| policy.allows(prev, **c)).collect::<Vec<_>>();
+        let c = **allowed.choose(rng)?;
+        password.push(c);
+        prev = Some(c);
+    }
+    Some(password)
+}
 
 /// 随机生成密码的需求：长度，大小写，数字，特殊字符，强度
 /// 随机数生成：rand crate
 /// 构建一个密码生成器
 /// 密码强度检测：zxcvbn crate
-
-pub fn process_genpass(length: u8, upper: bool, lower: bool, number: bool, symbol: bool) -> anyhow::Result<String> {
+pub fn process_genpass(policy: &PasswordPolicy) -> anyhow::Result<String> {
+    let (alphabet, classes) = policy.classes()?;
+    if !(1..=MAX_LENGTH).contains(&policy.length) {
+        anyhow::bail!("Length must be between 1 and {}, not {}", MAX_LENGTH, policy.length);
+    }
+    let length = policy.length as usize;
+    let required = classes.iter().map(|class| class.min).sum::<usize>();
+    if required > length {
+        anyhow::bail!("Length {} is too short for the {} characters the policy requires", length, required);
+    }
 
     let mut rng = thread_rng();
-    let mut password = Vec::new();
-    let mut chars = Vec::new();
+    for _ in 0..ATTEMPTS {
+        // The required characters take random positions, the rest may be anything from the alphabet
+        let mut slots = classes
+            .iter()
+            .flat_map(|class| std::iter::repeat_n(class.chars.as_slice(), class.min))
+            .collect::<Vec<_>>();
+        slots.resize(length, alphabet.as_slice());
+        slots.shuffle(&mut rng);
+        if let Some(password) = fill(&slots, policy, &mut rng) {
+            return Ok(password);
+        }
+    }
+    anyhow::bail!("No password of length {} satisfies --no-repeat and --no-sequential with these characters", length)
+}
+
+/// A generated password and its strength, one row of `rcli genpass --output`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GeneratedPassword {
+    pub password: String,
+    /// Bits of entropy, known for passphrases
+    pub entropy: Option<f64>,
+    #[serde(flatten)]
+    pub strength: Strength,
+}
+
+/// Random characters, a passphrase or a pattern, depending on the options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Generator {
+    Password(PasswordPolicy),
+    Passphrase(PassphrasePolicy),
+    Pattern(PatternPolicy),
+}
+
+impl TryFrom<&GenpassOpts> for Generator {
+    type Error = anyhow::Error;
+
+    fn try_from(opts: &GenpassOpts) -> anyhow::Result<Self> {
+        Ok(match opts.words {
+            Some(_) => Generator::Passphrase(PassphrasePolicy::try_from(opts)?),
+            None if opts.pattern.is_some() || opts.pronounceable => Generator::Pattern(PatternPolicy::try_from(opts)?),
+            None => Generator::Password(PasswordPolicy::from(opts)),
+        })
+    }
+}
+
+impl Generator {
+    /// Bits of entropy, exact for passphrases and patterns and an upper bound for passwords
+    pub fn entropy(&self) -> anyhow::Result<f64> {
+        match self {
+            Generator::Password(policy) => policy.entropy(),
+            Generator::Passphrase(policy) => Ok(policy.entropy()),
+            Generator::Pattern(policy) => policy.entropy(),
+        }
+    }
+
+    /// What to change when the output is too weak
+    fn remedy(&self) -> &'static str {
+        match self {
+            Generator::Password(_) => "raise --length or allow more characters",
+            Generator::Passphrase(_) => "raise --words or use a longer --wordlist",
+            Generator::Pattern(_) => "raise --length or add random characters to --pattern",
+        }
+    }
+
+    /// Generate until zxcvbn scores the result `min_score` or more, failing early when the options cannot get there
+    pub fn generate(&self, min_score: u8) -> anyhow::Result<GeneratedPassword> {
+        let needed = SCORE_GUESSES_LOG10[min_score.min(4) as usize];
+        let possible = self.entropy()? * 2f64.log10();
+        // Score 0 is no requirement, even a password with a single possible value meets it
+        if min_score > 0 && possible <= needed {
+            anyhow::bail!(
+                "--min-score {} needs more than 10^{} guesses, but these options allow at most 10^{:.1}, {}",
+                min_score,
+                needed,
+                possible,
+                self.remedy()
+            );
+        }
+        for _ in 0..STRENGTH_ATTEMPTS {
+            let (password, entropy) = match self {
+                Generator::Password(policy) => (process_genpass(policy)?, None),
+                Generator::Passphrase(policy) => {
+                    let passphrase = process_passphrase(policy)?;
+                    (passphrase.phrase, Some(passphrase.entropy))
+                },
+                Generator::Pattern(policy) => (process_pattern(policy)?, Some(policy.entropy()?)),
+            };
+            let strength = estimate_strength(&password, &[]);
+            if strength.score >= min_score {
+                return Ok(GeneratedPassword { password, entropy, strength });
+            }
+        }
+        anyhow::bail!("None of {} passwords scored {} or more, {}", STRENGTH_ATTEMPTS, min_score, self.remedy())
+    }
+}
+
+/// Write `opts.count` passwords or passphrases, as plain lines with the strength on stderr,
+/// or as rows of `opts.output` with the full zxcvbn estimate
+pub fn process_genpass_batch<W: Write>(opts: &GenpassOpts, mut writer: W) -> anyhow::Result<()> {
+    let generator = Generator::try_from(opts)?;
+    let Some(format) = opts.output else {
+        for _ in 0..opts.count {
+            let generated = generator.generate(opts.min_score)?;
+            writeln!(writer, "{}", generated.password)?;
+            if let Some(entropy) = generated.entropy {
+                eprintln!("Entropy: {:.1} bits", entropy);
+            }
+            eprintln!("Password strength: {}/4", generated.strength.score);
+        }
+        writer.flush()?;
+        return Ok(());
+    };
+    {
+        let mut rows = row_writer(format, ',', &mut writer);
+        for _ in 0..opts.count {
+            rows.write_row(&serde_json::to_value(generator.generate(opts.min_score)?)?)?;
+        }
+        rows.finish()?;
+    }
+    // The caller's writer stands for stdout
+    end_output(&mut writer, format, "-")
+}
 
-    if upper {
-        chars.extend_from_slice(UPPER);
-        password.push(*UPPER.choose(&mut rng).expect("No uppercase characters available for password generation"));
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use proptest::prelude::*;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy::default()
+    }
+
+    #[test]
+    fn test_genpass_default() -> anyhow::Result<()> {
+        let password = process_genpass(&policy())?;
+        assert_eq!(password.len(), 16);
+        assert!(password.chars().any(is_upper) && password.chars().any(char::is_lowercase));
+        assert!(password.chars().any(is_digit) && password.chars().any(is_symbol));
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_disabled_classes() -> anyhow::Result<()> {
+        let pin = PasswordPolicy { length: 6, upper: false, lower: false, symbol: false, ..policy() };
+        let password = process_genpass(&pin)?;
+        assert_eq!(password.len(), 6);
+        assert!(password.chars().all(is_digit));
+
+        let password = process_genpass(&PasswordPolicy { symbol: false, number: false, ..policy() })?;
+        assert!(password.chars().all(char::is_alphabetic));
+
+        let none = PasswordPolicy { number: false, ..pin };
+        assert_eq!(process_genpass(&none).unwrap_err().to_string(), "Every character class is disabled, keep at least one or pass --charset");
+        let err = process_genpass(&PasswordPolicy { number: false, min_digits: 2, ..policy() }).unwrap_err();
+        assert_eq!(err.to_string(), "--min-digits 2 needs digits enabled");
+        Ok(())
+    }
+
+    #[test]
+    fn test_genpass_custom_sets() -> anyhow::Result<()> {
+        let password = process_genpass(&PasswordPolicy { charset: Some("ab".into()), length: 32, no_repeat: true, ..policy() })?;
+        assert!(password == "ab".repeat(16) || password == "ba".repeat(16));
+
+        let password = process_genpass(&PasswordPolicy { symbols: Some("+".into()), min_symbols: 5, ..policy() })?;
+        assert_eq!(password.chars().filter(|c| *c == '+').count(), password.chars().filter(|c| is_symbol(*c)).count());
+        assert!(password.chars().filter(|c| *c == '+').count() >= 5);
+
+        let password = process_genpass(&PasswordPolicy { charset: Some("0123456789".into()), exclude: "0".into(), ..policy() })?;
+        assert!(password.chars().all(|c| ('1'..='9').contains(&c)));
+        Ok(())
     }
 
-    if lower {
-        chars.extend_from_slice(LOWER);
-        password.push(*LOWER.choose(&mut rng).expect("No lowercase characters available for password generation"));
+    #[test]
+    fn test_genpass_lengths() -> anyhow::Result<()> {
+        for length in [1, 4, 255, 256, MAX_LENGTH] {
+            let classes = if length < 4 { PasswordPolicy { upper: false, lower: false, symbol: false, ..policy() } } else { policy() };
+            let password = process_genpass(&PasswordPolicy { length, no_repeat: true, no_sequential: true, ..classes })?;
+            assert_eq!(password.chars().count(), length as usize);
+        }
+        Ok(())
     }
 
-    if number {
-        chars.extend_from_slice(NUMBER);
-        password.push(*NUMBER.choose(&mut rng).expect("No numeric characters available for password generation"));
+    #[test]
+    fn test_genpass_policy_errors() {
+        let err = |policy: PasswordPolicy| process_genpass(&policy).unwrap_err().to_string();
+        assert_eq!(err(PasswordPolicy { length: 0, ..policy() }), "Length must be between 1 and 4096, not 0");
+        assert_eq!(err(PasswordPolicy { length: 4097, ..policy() }), "Length must be between 1 and 4096, not 4097");
+        // Fewer characters than enabled classes, each of which needs one
+        assert_eq!(err(PasswordPolicy { length: 3, ..policy() }), "Length 3 is too short for the 4 characters the policy requires");
+        assert_eq!(err(PasswordPolicy { length: 8, min_digits: 4, min_symbols: 3, ..policy() }), "Length 8 is too short for the 9 characters the policy requires");
+        assert_eq!(err(PasswordPolicy { exclude: NUMBER.into(), ..policy() }), "--exclude leaves no digits");
+        assert_eq!(err(PasswordPolicy { symbols: Some("a!".into()), ..policy() }), "--symbols must not contain letters or digits, found 'a'");
+        assert_eq!(err(PasswordPolicy { charset: Some("abc".into()), min_upper: 1, ..policy() }), "--min-upper 1 needs uppercase letters in --charset");
+        assert_eq!(err(PasswordPolicy { charset: Some("x".into()), exclude: "x".into(), ..policy() }), "--charset has no characters left after --exclude");
+        assert_eq!(
+            err(PasswordPolicy { charset: Some("a".into()), no_repeat: true, ..policy() }),
+            "No password of length 16 satisfies --no-repeat and --no-sequential with these characters"
+        );
     }
 
-    if symbol {
-        chars.extend_from_slice(SYMBOL);
-        password.push(*SYMBOL.choose(&mut rng).expect("No symbol characters available for password generation"));
+    fn batch(args: &[&str]) -> anyhow::Result<String> {
+        let opts = GenpassOpts::parse_from(["genpass"].iter().chain(args));
+        let mut output = Vec::new();
+        process_genpass_batch(&opts, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_genpass_batch() -> anyhow::Result<()> {
+        let output = batch(&["-c", "3", "-l", "12"])?;
+        assert_eq!(output.lines().count(), 3);
+        assert!(output.lines().all(|line| line.chars().count() == 12));
+
+        let rows: Vec<Value> = serde_json::from_str(&batch(&["-c", "2", "--output", "json"])?)?;
+        assert_eq!(rows.len(), 2);
+        let keys = rows[0].as_object().unwrap().keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            [
+                "password",
+                "entropy",
+                "score",
+                "guesses",
+                "guesses_log10",
+                "crack_time_online_throttled",
+                "crack_time_online",
+                "crack_time_offline_slow",
+                "crack_time_offline_fast",
+                "warning",
+                "suggestions"
+            ]
+        );
+        assert_eq!(rows[0]["entropy"], Value::Null);
+
+        let output = batch(&["-c", "4", "--words", "3", "--output", "csv"])?;
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        assert_eq!(reader.headers()?.get(1), Some("entropy"));
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(records.len(), 4);
+        assert!(records.iter().all(|record| record[0].split('-').count() == 3 && record[1].starts_with("38.77")));
+        Ok(())
     }
 
-    for _ in 0..length - password.len() as u8 {
-        // Randomly choose a character from the available characters
-        // Ensure that we have at least one of each type already in the password
-        let c = chars.choose(&mut rng).expect("No characters available for password generation");
-        password.push(*c);
+    #[test]
+    fn test_genpass_min_score() -> anyhow::Result<()> {
+        let output = batch(&["-c", "5", "--min-score", "4", "--output", "ndjson"])?;
+        for line in output.lines() {
+            assert_eq!(serde_json::from_str::<Value>(line)?["score"], 4);
+        }
+
+        assert_eq!(batch(&["--charset", "a", "-l", "4"])?, "aaaa\n");
+        let err = batch(&["-l", "4", "--no-uppercase", "--no-lowercase", "--no-symbol", "--min-score", "2"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--min-score 2 needs more than 10^6 guesses, but these options allow at most 10^3.8, raise --length or allow more characters"
+        );
+        let err = batch(&["--words", "2", "--min-score", "4"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--min-score 4 needs more than 10^10 guesses, but these options allow at most 10^7.8, raise --words or use a longer --wordlist"
+        );
+        Ok(())
     }
-    // Shuffle the password to ensure randomness
 
-    password.shuffle(&mut rng);
+    #[test]
+    fn test_genpass_pattern() -> anyhow::Result<()> {
+        let output = batch(&["--pattern", "Cvccvc-99-Cvccvc", "-c", "3", "--output", "ndjson"])?;
+        for line in output.lines() {
+            let row = serde_json::from_str::<Value>(line)?;
+            let password = row["password"].as_str().unwrap_or_default();
+            assert_eq!((password.len(), &password[6..7], &password[9..10]), (16, "-", "-"));
+            let expected = 20f64.log2() * 8.0 + 4f64.log2() * 4.0 + 9f64.log2() * 2.0;
+            assert!((row["entropy"].as_f64().unwrap_or_default() - expected).abs() < 1e-9);
+            assert!(row["score"].is_u64());
+        }
+        assert_eq!(batch(&["--pronounceable", "-l", "12"])?.trim().len(), 12);
 
-    let password = String::from_utf8(password)?;
+        let err = batch(&["--pattern", "Cv-99", "--min-score", "3"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--min-score 3 needs more than 10^8 guesses, but these options allow at most 10^3.8, raise --length or add random characters to --pattern"
+        );
+        Ok(())
+    }
 
-    Ok(password)
+    proptest! {
+        #[test]
+        fn prop_genpass_meets_policy(
+            length in 8u16..=64,
+            min_upper in 0usize..3,
+            min_digits in 0usize..3,
+            min_symbols in 0usize..3,
+            exclude in "[A-Za-z0-9!@#]{0,8}",
+            no_repeat: bool,
+            no_sequential: bool,
+        ) {
+            let policy = PasswordPolicy { length, min_upper, min_digits, min_symbols, exclude, no_repeat, no_sequential, ..policy() };
+            let password = process_genpass(&policy).unwrap();
+            let chars = password.chars().collect::<Vec<_>>();
+            prop_assert_eq!(chars.len(), length as usize);
+            prop_assert!(chars.iter().all(|c| !policy.exclude.contains(*c)));
+            prop_assert!(chars.iter().filter(|c| is_upper(**c)).count() >= min_upper.max(1));
+            prop_assert!(chars.iter().filter(|c| c.is_lowercase()).count() >= 1);
+            prop_assert!(chars.iter().filter(|c| is_digit(**c)).count() >= min_digits.max(1));
+            prop_assert!(chars.iter().filter(|c| is_symbol(**c)).count() >= min_symbols.max(1));
+            for pair in chars.windows(2) {
+                prop_assert!(!no_repeat || pair[0] != pair[1], "{} repeats", password);
+                prop_assert!(!no_sequential || !is_sequential(pair[0], pair[1]), "{} is sequential", password);
+            }
+        }
+
+        #[test]
+        fn prop_genpass_charset(charset in "[a-f0-9#]{2,10}", length in 1u16..=32, min_digits in 0usize..2) {
+            prop_assume!(min_digits == 0 || charset.chars().any(is_digit));
+            let policy = PasswordPolicy { charset: Some(charset.clone()), length, min_digits, no_repeat: true, ..policy() };
+            match process_genpass(&policy) {
+                Ok(password) => {
+                    prop_assert!(password.chars().all(|c| charset.contains(c)));
+                    prop_assert!(password.chars().filter(|c| is_digit(*c)).count() >= min_digits);
+                    prop_assert!(password.chars().zip(password.chars().skip(1)).all(|(a, b)| a != b));
+                },
+                // Only a charset of one distinct character cannot avoid repeats
+                Err(_) => prop_assert!(charset.chars().all(|c| charset.starts_with(c)) && length > 1),
+            }
+        }
+    }
 }
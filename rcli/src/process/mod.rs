@@ -1,11 +1,42 @@
+mod csv_cat;
+mod csv_columnar;
 mod csv_convert;
+mod csv_diff;
+mod csv_parallel;
+mod csv_join;
+mod csv_query;
+mod csv_record;
+mod csv_sheet;
+mod csv_show;
+mod csv_stats;
+mod csv_types;
+mod csv_validate;
+mod flatten;
+mod to_csv;
 mod gen_pass;
+mod pass_check;
+mod passphrase;
+mod pattern;
+mod strength;
 mod b64;
 mod text;
 mod http_serve;
 
-pub use csv_convert::process_csv;
-pub use gen_pass::process_genpass;
+pub use csv_convert::{process_csv, convert_csv};
+pub use csv_cat::process_csv_cat;
+pub use csv_diff::process_csv_diff;
+pub use csv_parallel::{convert_csv_parallel, CHUNK_SIZE};
+pub use csv_record::{FieldSpec, FieldType, RecordDecoder, RecordSchema};
+pub use csv_join::process_csv_join;
+pub use csv_show::process_csv_show;
+pub use csv_stats::process_csv_stats;
+pub use csv_validate::process_csv_validate;
+pub use to_csv::process_to_csv;
+pub use gen_pass::{process_genpass, process_genpass_batch, GeneratedPassword, Generator, PasswordPolicy, MAX_LENGTH};
+pub use pass_check::{check_passwords, process_genpass_check, CheckedPassword};
+pub use strength::{estimate_strength, Strength};
+pub use passphrase::{parse_wordlist, process_passphrase, Passphrase, PassphrasePolicy};
+pub use pattern::{process_pattern, PatternPolicy};
 pub use b64::{process_decode, process_encode};
 pub use text::{process_sign, process_verify, process_generate};
 pub use http_serve::process_http_serve;
@@ -0,0 +1,109 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use serde::Serialize;
+
+use crate::{cli::GenpassCheckOpts, get_reader};
+
+use super::{csv_convert::{end_output, row_writer}, csv_show::write_table, strength::{estimate_strength, Strength}};
+
+/// The verdict on one password of the input, which is identified by its line and never echoed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckedPassword {
+    pub line: usize,
+    /// Whether the score reaches the minimum
+    pub ok: bool,
+    #[serde(flatten)]
+    pub strength: Strength,
+}
+
+/// Estimate every password of `reader`, one per line, skipping empty lines
+pub fn check_passwords<R: Read>(reader: R, context: &[String], min_score: u8) -> anyhow::Result<Vec<CheckedPassword>> {
+    let context = context.iter().map(String::as_str).collect::<Vec<_>>();
+    let mut checked = Vec::new();
+    for (i, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let password = line.strip_suffix('\r').unwrap_or(&line);
+        if password.is_empty() {
+            continue;
+        }
+        let strength = estimate_strength(password, &context);
+        checked.push(CheckedPassword { line: i + 1, ok: strength.score >= min_score, strength });
+    }
+    Ok(checked)
+}
+
+fn write_check_table<W: Write>(writer: &mut W, checked: &[CheckedPassword]) -> anyhow::Result<()> {
+    let headers = ["line", "score", "ok", "crack time (offline, slow hash)", "warning", "suggestions"].map(String::from);
+    let rows = checked
+        .iter()
+        .map(|c| {
+            vec![
+                c.line.to_string(),
+                c.strength.score.to_string(),
+                if c.ok { "yes" } else { "no" }.to_string(),
+                c.strength.crack_time_offline_slow.clone(),
+                c.strength.warning.clone().unwrap_or_default(),
+                c.strength.suggestions.join(" "),
+            ]
+        })
+        .collect::<Vec<_>>();
+    write_table(writer, &headers, &rows, 40, false)
+}
+
+pub fn process_genpass_check(opts: &GenpassCheckOpts) -> anyhow::Result<()> {
+    let checked = check_passwords(get_reader(&opts.input)?, &opts.context, opts.min_score)?;
+    let mut stdout = io::stdout().lock();
+    match opts.format {
+        Some(format) => {
+            {
+                let mut writer = row_writer(format, ',', &mut stdout);
+                for password in &checked {
+                    writer.write_row(&serde_json::to_value(password)?)?;
+                }
+                writer.finish()?;
+            }
+            end_output(&mut stdout, format, "-")?;
+        },
+        None => write_check_table(&mut stdout, &checked)?,
+    }
+    stdout.flush()?;
+    let weak = checked.iter().filter(|c| !c.ok).count();
+    if weak > 0 {
+        anyhow::bail!("{} of {} passwords score below {}", weak, checked.len(), opts.min_score);
+    }
+    eprintln!("{} passwords score {} or more", checked.len(), opts.min_score);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_passwords() -> anyhow::Result<()> {
+        let input = "password\r\n\nX7eP3h$#7c6X3YH#\nalice2024\n";
+        let checked = check_passwords(input.as_bytes(), &[], 3)?;
+        assert_eq!(checked.iter().map(|c| (c.line, c.ok)).collect::<Vec<_>>(), [(1, false), (3, true), (4, false)]);
+        assert_eq!(checked[0].strength.score, 0);
+        assert!(checked[0].strength.warning.is_some());
+
+        // A context word turns a made-up word into a dictionary one
+        let plain = check_passwords("Zorblatt2024!".as_bytes(), &[], 0)?;
+        let context = check_passwords("Zorblatt2024!".as_bytes(), &["zorblatt".into()], 0)?;
+        assert!(context[0].strength.guesses < plain[0].strength.guesses);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_table() -> anyhow::Result<()> {
+        let checked = check_passwords("password\n".as_bytes(), &[], 1)?;
+        let mut output = Vec::new();
+        write_check_table(&mut output, &checked)?;
+        let output = String::from_utf8(output)?;
+        let lines = output.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("line"));
+        assert!(lines.iter().any(|line| line.contains("This is a top-10 common password")));
+        assert!(!output.contains("| password"));
+        Ok(())
+    }
+}
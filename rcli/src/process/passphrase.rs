@@ -0,0 +1,161 @@
+use std::{collections::HashSet, io::Read};
+
+use rand::{seq::SliceRandom, thread_rng, Rng};
+
+use crate::{cli::GenpassOpts, get_reader};
+
+/// 7776 common English words, as many as five dice can pick from, see assets/README.md
+const WORDLIST: &str = include_str!("../../assets/wordlist.txt");
+
+/// What a generated passphrase has to look like
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassphrasePolicy {
+    pub words: usize,
+    /// Distinct words to pick from
+    pub wordlist: Vec<String>,
+    pub separator: String,
+    pub capitalize: bool,
+    /// Random digits appended to random words
+    pub digits: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passphrase {
+    pub phrase: String,
+    /// Bits of entropy given the policy, what an attacker knowing the word list and options is up against
+    pub entropy: f64,
+}
+
+/// Words of a list file, one per line. Leading dice numbers as in "11111\tabacus" and duplicates are dropped
+pub fn parse_wordlist(content: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut seen = HashSet::new();
+    for line in content.lines() {
+        let word = match line.split_once(char::is_whitespace) {
+            Some((dice, word)) if dice.chars().all(|c| c.is_ascii_digit()) => word.trim(),
+            _ => line.trim(),
+        };
+        if !word.is_empty() && seen.insert(word) {
+            words.push(word.to_string());
+        }
+    }
+    words
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        PassphrasePolicy {
+            words: 6,
+            wordlist: parse_wordlist(WORDLIST),
+            separator: "-".into(),
+            capitalize: false,
+            digits: 0,
+        }
+    }
+}
+
+impl TryFrom<&GenpassOpts> for PassphrasePolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(opts: &GenpassOpts) -> anyhow::Result<Self> {
+        let wordlist = match &opts.wordlist {
+            Some(path) => {
+                let mut content = String::new();
+                get_reader(path)?.read_to_string(&mut content)?;
+                parse_wordlist(&content)
+            },
+            None => parse_wordlist(WORDLIST),
+        };
+        Ok(PassphrasePolicy {
+            words: opts.words.unwrap_or(6),
+            wordlist,
+            separator: opts.separator.clone(),
+            capitalize: opts.capitalize,
+            digits: opts.digits,
+        })
+    }
+}
+
+impl PassphrasePolicy {
+    /// Each word adds log2 of the list size, each digit log2 of 10 digits times the words it may follow
+    pub fn entropy(&self) -> f64 {
+        self.words as f64 * (self.wordlist.len() as f64).log2() + self.digits as f64 * (10.0 * self.words as f64).log2()
+    }
+}
+
+fn capitalized(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Diceware style passphrase: words drawn uniformly and independently from the list
+pub fn process_passphrase(policy: &PassphrasePolicy) -> anyhow::Result<Passphrase> {
+    if policy.words == 0 {
+        anyhow::bail!("A passphrase needs at least one word");
+    }
+    if policy.wordlist.len() < 2 {
+        anyhow::bail!("The word list needs at least two distinct words, it has {}", policy.wordlist.len());
+    }
+    let mut rng = thread_rng();
+    let mut words = (0..policy.words)
+        .map(|_| {
+            let word = policy.wordlist.choose(&mut rng).expect("word list is not empty");
+            if policy.capitalize { capitalized(word) } else { word.clone() }
+        })
+        .collect::<Vec<_>>();
+    for _ in 0..policy.digits {
+        let i = rng.gen_range(0..words.len());
+        let digit = rng.gen_range(0..10);
+        words[i].push(char::from_digit(digit, 10).expect("digit is below 10"));
+    }
+    Ok(Passphrase { phrase: words.join(&policy.separator), entropy: policy.entropy() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_wordlist() {
+        let words = parse_wordlist(WORDLIST);
+        assert_eq!(words.len(), 7776);
+        assert!(words.iter().all(|word| word.chars().all(|c| c.is_ascii_lowercase())));
+        let entropy = PassphrasePolicy::default().entropy();
+        assert!((entropy - 6.0 * 7776f64.log2()).abs() < 1e-9, "{entropy}");
+    }
+
+    #[test]
+    fn test_parse_wordlist() {
+        assert_eq!(parse_wordlist("11111\tabacus\n11112 abdomen\n\nzebra\nzebra\n  apple pie \n"), ["abacus", "abdomen", "zebra", "apple pie"]);
+    }
+
+    #[test]
+    fn test_passphrase() -> anyhow::Result<()> {
+        let policy = PassphrasePolicy { words: 4, ..Default::default() };
+        let passphrase = process_passphrase(&policy)?;
+        let words = passphrase.phrase.split('-').collect::<Vec<_>>();
+        assert_eq!(words.len(), 4);
+        assert!(words.iter().all(|word| policy.wordlist.iter().any(|w| w == word)));
+        assert!((passphrase.entropy - 4.0 * 7776f64.log2()).abs() < 1e-9);
+
+        let wordlist = vec!["alpha".to_string(), "beta".to_string()];
+        let policy = PassphrasePolicy { words: 3, wordlist, separator: " ".into(), capitalize: true, digits: 2 };
+        let passphrase = process_passphrase(&policy)?;
+        assert_eq!(passphrase.phrase.split(' ').count(), 3);
+        assert!(passphrase.phrase.split(' ').all(|word| word.starts_with("Alpha") || word.starts_with("Beta")));
+        assert_eq!(passphrase.phrase.chars().filter(char::is_ascii_digit).count(), 2);
+        assert!((passphrase.entropy - (3.0 + 2.0 * 30f64.log2())).abs() < 1e-9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_passphrase_errors() {
+        let err = process_passphrase(&PassphrasePolicy { words: 0, ..Default::default() }).unwrap_err();
+        assert_eq!(err.to_string(), "A passphrase needs at least one word");
+        let policy = PassphrasePolicy { wordlist: vec!["only".into()], ..Default::default() };
+        assert_eq!(process_passphrase(&policy).unwrap_err().to_string(), "The word list needs at least two distinct words, it has 1");
+    }
+}
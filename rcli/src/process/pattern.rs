@@ -0,0 +1,158 @@
+use rand::{seq::SliceRandom, thread_rng};
+
+use crate::cli::GenpassOpts;
+
+use super::gen_pass::SYMBOL;
+
+/// The letters of genpass, which leave out l and o as too easily confused with 1 and 0
+const CONSONANT: &str = "bcdfghjkmnpqrstvwxyz";
+const VOWEL: &str = "aeiu";
+const DIGIT: &str = "123456789";
+
+/// A template such as "Cvccvc-99-Cvccvc": C and c are consonants, V and v vowels, in upper and lower case,
+/// 9 is a digit and ! a symbol. A backslash escapes the next character, everything else is kept as is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternPolicy {
+    pub pattern: String,
+    /// Replaces the default symbols for !
+    pub symbols: Option<String>,
+}
+
+/// One position of a pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Slot {
+    Random(Vec<char>),
+    Literal(char),
+}
+
+impl TryFrom<&GenpassOpts> for PatternPolicy {
+    type Error = anyhow::Error;
+
+    fn try_from(opts: &GenpassOpts) -> anyhow::Result<Self> {
+        let pattern = match &opts.pattern {
+            Some(pattern) => pattern.clone(),
+            None if opts.pronounceable => PatternPolicy::pronounceable(opts.length as usize).pattern,
+            None => anyhow::bail!("Neither --pattern nor --pronounceable was given"),
+        };
+        Ok(PatternPolicy { pattern, symbols: opts.symbols.clone() })
+    }
+}
+
+impl PatternPolicy {
+    /// Alternating consonants and vowels starting with a capital, as in "Kodabute"
+    pub fn pronounceable(length: usize) -> Self {
+        let pattern = (0..length).map(|i| match i {
+            0 => 'C',
+            i if i % 2 == 0 => 'c',
+            _ => 'v',
+        });
+        PatternPolicy { pattern: pattern.collect(), symbols: None }
+    }
+
+    fn slots(&self) -> anyhow::Result<Vec<Slot>> {
+        let symbols = self.symbols.as_deref().unwrap_or(SYMBOL);
+        if let Some(c) = symbols.chars().find(|c| c.is_alphanumeric()) {
+            anyhow::bail!("--symbols must not contain letters or digits, found '{}'", c);
+        }
+        let mut symbols = symbols.chars().collect::<Vec<_>>();
+        symbols.sort_unstable();
+        symbols.dedup();
+
+        let mut slots = Vec::new();
+        let mut chars = self.pattern.chars();
+        while let Some(c) = chars.next() {
+            slots.push(match c {
+                'C' => Slot::Random(CONSONANT.to_uppercase().chars().collect()),
+                'c' => Slot::Random(CONSONANT.chars().collect()),
+                'V' => Slot::Random(VOWEL.to_uppercase().chars().collect()),
+                'v' => Slot::Random(VOWEL.chars().collect()),
+                '9' => Slot::Random(DIGIT.chars().collect()),
+                '!' if symbols.is_empty() => anyhow::bail!("--pattern uses '!' but --symbols is empty"),
+                '!' => Slot::Random(symbols.clone()),
+                '\\' => match chars.next() {
+                    Some(escaped) => Slot::Literal(escaped),
+                    None => anyhow::bail!("--pattern '{}' ends with an unfinished escape", self.pattern),
+                },
+                c => Slot::Literal(c),
+            });
+        }
+        if !slots.iter().any(|slot| matches!(slot, Slot::Random(_))) {
+            anyhow::bail!("--pattern '{}' has no random characters, use C, c, V, v, 9 or !", self.pattern);
+        }
+        Ok(slots)
+    }
+
+    /// Each random position adds log2 of its choices, literals add nothing
+    pub fn entropy(&self) -> anyhow::Result<f64> {
+        Ok(self
+            .slots()?
+            .iter()
+            .map(|slot| match slot {
+                Slot::Random(chars) => (chars.len() as f64).log2(),
+                Slot::Literal(_) => 0.0,
+            })
+            .sum())
+    }
+}
+
+/// Fill every random position of the pattern independently
+pub fn process_pattern(policy: &PatternPolicy) -> anyhow::Result<String> {
+    let mut rng = thread_rng();
+    let password = policy
+        .slots()?
+        .iter()
+        .map(|slot| match slot {
+            Slot::Random(chars) => *chars.choose(&mut rng).expect("slot has choices"),
+            Slot::Literal(c) => *c,
+        })
+        .collect();
+    Ok(password)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(pattern: &str) -> PatternPolicy {
+        PatternPolicy { pattern: pattern.into(), symbols: None }
+    }
+
+    #[test]
+    fn test_pattern() -> anyhow::Result<()> {
+        let policy = pattern("Cvccvc-99-\\C!");
+        let password = process_pattern(&policy)?;
+        let chars = password.chars().collect::<Vec<_>>();
+        assert_eq!(chars.len(), 12);
+        assert!(CONSONANT.to_uppercase().contains(chars[0]) && VOWEL.contains(chars[1]) && CONSONANT.contains(chars[2]));
+        assert_eq!((chars[6], chars[9], chars[10]), ('-', '-', 'C'));
+        assert!(chars[7].is_ascii_digit() && SYMBOL.contains(chars[11]));
+
+        let expected = 20f64.log2() * 4.0 + 4f64.log2() * 2.0 + 9f64.log2() * 2.0 + 10f64.log2();
+        assert!((policy.entropy()? - expected).abs() < 1e-9);
+
+        let policy = PatternPolicy { pattern: "!!".into(), symbols: Some("##+".into()) };
+        assert!((policy.entropy()? - 2.0).abs() < 1e-9);
+        assert!(process_pattern(&policy)?.chars().all(|c| c == '#' || c == '+'));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pronounceable() -> anyhow::Result<()> {
+        assert_eq!(PatternPolicy::pronounceable(6).pattern, "Cvcvcv");
+        let password = process_pattern(&PatternPolicy::pronounceable(9))?;
+        assert_eq!(password.len(), 9);
+        assert!(password.chars().skip(1).step_by(2).all(|c| VOWEL.contains(c)));
+        assert!(password.chars().step_by(2).skip(1).all(|c| CONSONANT.contains(c)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        let err = |policy: PatternPolicy| process_pattern(&policy).unwrap_err().to_string();
+        assert_eq!(err(pattern("xyz-")), "--pattern 'xyz-' has no random characters, use C, c, V, v, 9 or !");
+        assert_eq!(err(pattern("")), "--pattern '' has no random characters, use C, c, V, v, 9 or !");
+        assert_eq!(err(pattern("99\\")), "--pattern '99\\' ends with an unfinished escape");
+        assert_eq!(err(PatternPolicy { pattern: "9!".into(), symbols: Some(String::new()) }), "--pattern uses '!' but --symbols is empty");
+        assert_eq!(err(PatternPolicy { pattern: "9".into(), symbols: Some("a".into()) }), "--symbols must not contain letters or digits, found 'a'");
+    }
+}
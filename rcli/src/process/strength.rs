@@ -0,0 +1,63 @@
+use serde::Serialize;
+use zxcvbn::zxcvbn;
+
+/// zxcvbn's verdict on a password, flat so every format can hold it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Strength {
+    /// 0 (too guessable) to 4 (very unguessable)
+    pub score: u8,
+    pub guesses: u64,
+    pub guesses_log10: f64,
+    /// Online attack throttled to 100 guesses per hour
+    pub crack_time_online_throttled: String,
+    /// Online attack at 10 guesses per second
+    pub crack_time_online: String,
+    /// Offline attack on a slow hash at 1e4 guesses per second
+    pub crack_time_offline_slow: String,
+    /// Offline attack on a fast hash at 1e10 guesses per second
+    pub crack_time_offline_fast: String,
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>,
+}
+
+/// Estimate the strength of `password`, counting `user_inputs` such as a user or company name as easy guesses
+pub fn estimate_strength(password: &str, user_inputs: &[&str]) -> Strength {
+    let entropy = zxcvbn(password, user_inputs);
+    let times = entropy.crack_times();
+    let feedback = entropy.feedback();
+    Strength {
+        score: entropy.score().into(),
+        guesses: entropy.guesses(),
+        guesses_log10: entropy.guesses_log10(),
+        crack_time_online_throttled: times.online_throttling_100_per_hour().to_string(),
+        crack_time_online: times.online_no_throttling_10_per_second().to_string(),
+        crack_time_offline_slow: times.offline_slow_hashing_1e4_per_second().to_string(),
+        crack_time_offline_fast: times.offline_fast_hashing_1e10_per_second().to_string(),
+        warning: feedback.and_then(|feedback| feedback.warning()).map(|warning| warning.to_string()),
+        suggestions: feedback
+            .map(|feedback| feedback.suggestions().iter().map(ToString::to_string).collect())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_strength() {
+        let weak = estimate_strength("password", &[]);
+        assert_eq!(weak.score, 0);
+        assert_eq!(weak.crack_time_offline_fast, "less than a second");
+        assert!(weak.warning.is_some());
+        assert!(!weak.suggestions.is_empty());
+
+        let strong = estimate_strength("X7eP3h$#7c6X3YH#", &[]);
+        assert_eq!(strong.score, 4);
+        assert_eq!(strong.warning, None);
+        assert!(strong.guesses_log10 > 10.0);
+
+        // Context words make an otherwise decent password guessable
+        assert!(estimate_strength("juventus1897", &["juventus"]).guesses < estimate_strength("juventus1897", &[]).guesses);
+    }
+}
@@ -6,7 +6,7 @@ use rand::rngs::OsRng;
 
 use crate::{get_reader, TextSignFormat};
 
-use super::process_genpass;
+use super::{process_genpass, PasswordPolicy};
 
 
 pub trait TextSign {
@@ -59,7 +59,7 @@ impl TextVerify for Blake3 {
 
 impl KeyGenerator for Blake3 {
     fn generate() -> anyhow::Result<Vec<Vec<u8>>> {
-        let key = process_genpass(32, true, true, true, true)?;
+        let key = process_genpass(&PasswordPolicy { length: 32, ..Default::default() })?;
         let key = key.as_bytes().to_vec();
         Ok(vec![key])
     }
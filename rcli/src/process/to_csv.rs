@@ -0,0 +1,144 @@
+use std::{collections::{HashMap, HashSet}, io::{Read, Write}, path::Path};
+
+use serde_json::{Map, Value};
+
+use crate::{cli::{OutputFormat, ToCsvOpts}, get_reader, get_writer};
+
+use super::{csv_convert::cell_text, flatten::flatten_object};
+
+/// Parse an array of objects from JSON, YAML, TOML or NDJSON text
+pub fn parse_rows(content: &str, format: OutputFormat) -> anyhow::Result<Vec<Value>> {
+    let value = match format {
+        OutputFormat::Json => serde_json::from_str(content)?,
+        OutputFormat::Yaml => serde_yaml::from_str(content)?,
+        OutputFormat::Toml => {
+            // Rows live under a single top-level array table such as `[[rows]]`
+            let table: Map<String, Value> = toml::from_str(content)?;
+            if table.len() == 1 && table.values().all(Value::is_array) {
+                table.into_iter().map(|(_, rows)| rows).next().unwrap_or_default()
+            } else {
+                Value::Object(table)
+            }
+        },
+        OutputFormat::Ndjson => {
+            let rows = content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<Vec<Value>, _>>()?;
+            Value::Array(rows)
+        },
+        _ => anyhow::bail!("Unsupported input format: '{}'", format),
+    };
+    match value {
+        Value::Array(rows) => Ok(rows),
+        // A single object is a single row
+        Value::Object(_) => Ok(vec![value]),
+        _ => anyhow::bail!("Expected an array of objects"),
+    }
+}
+
+/// Write rows as CSV, the header is the union of all flattened keys in order of first appearance
+pub fn rows_to_csv<W: Write>(rows: &[Value], writer: W, delimiter: char) -> anyhow::Result<()> {
+    let mut header = Vec::new();
+    let mut seen = HashSet::new();
+    let mut flattened = Vec::with_capacity(rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        let Value::Object(object) = row else {
+            anyhow::bail!("Row {} is not an object", i + 1);
+        };
+        let fields = flatten_object(object);
+        for (key, _) in &fields {
+            if seen.insert(key.clone()) {
+                header.push(key.clone());
+            }
+        }
+        flattened.push(fields.into_iter().collect::<HashMap<_, _>>());
+    }
+
+    // Without columns there is nothing to write, an empty record would come out as `""`
+    if header.is_empty() {
+        return Ok(());
+    }
+    let mut writer = csv::WriterBuilder::new().delimiter(delimiter as u8).from_writer(writer);
+    writer.write_record(&header)?;
+    for fields in &flattened {
+        let cells = header.iter().map(|key| fields.get(key).map(cell_text).unwrap_or_default()).collect::<Vec<_>>();
+        writer.write_record(cells.iter().map(|cell| cell.as_bytes()))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Input format from the file extension, json when unknown
+fn guess_format(input: &str) -> OutputFormat {
+    match Path::new(input).extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => OutputFormat::Yaml,
+        Some("toml") => OutputFormat::Toml,
+        Some("ndjson" | "jsonl") => OutputFormat::Ndjson,
+        _ => OutputFormat::Json,
+    }
+}
+
+pub fn process_to_csv(opts: &ToCsvOpts) -> anyhow::Result<()> {
+    let format = opts.format.unwrap_or_else(|| guess_format(&opts.input));
+    let mut reader = get_reader(&opts.input)?;
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+
+    let rows = parse_rows(&content, format)?;
+    let writer = get_writer(&opts.output)?;
+    rows_to_csv(&rows, writer, opts.delimiter)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn to_csv(content: &str, format: OutputFormat) -> anyhow::Result<String> {
+        let mut output = Vec::new();
+        rows_to_csv(&parse_rows(content, format)?, &mut output, ',')?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_to_csv_fixtures() -> anyhow::Result<()> {
+        let expected = std::fs::read_to_string("fixtures/players.csv")?;
+        for (input, format) in [
+            ("fixtures/players.json", OutputFormat::Json),
+            ("fixtures/players.yaml", OutputFormat::Yaml),
+            ("fixtures/players.toml", OutputFormat::Toml),
+            ("fixtures/players.ndjson", OutputFormat::Ndjson),
+        ] {
+            assert_eq!(guess_format(input), format);
+            assert_eq!(to_csv(&std::fs::read_to_string(input)?, format)?, expected, "{input}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_csv_union_and_flatten() -> anyhow::Result<()> {
+        let content = json!([
+            {"name": "Buffon", "address": {"city": "Turin"}, "tags": ["keeper"]},
+            {"name": "Perin", "kit": 37, "address": {"city": "Genoa", "zip": "16100"}, "active": true, "note": null},
+        ]);
+        let output = to_csv(&content.to_string(), OutputFormat::Json)?;
+        assert_eq!(
+            output,
+            "name,address.city,tags.0,kit,address.zip,active,note\nBuffon,Turin,keeper,,,,\nPerin,Genoa,,37,16100,true,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_csv_errors() {
+        assert!(to_csv("[1, 2]", OutputFormat::Json).is_err());
+        assert!(to_csv("\"text\"", OutputFormat::Json).is_err());
+        assert!(to_csv("a,b", OutputFormat::Csv).is_err());
+        assert_eq!(to_csv("name: Buffon", OutputFormat::Yaml).unwrap(), "name\nBuffon\n");
+        assert_eq!(to_csv("[]", OutputFormat::Json).unwrap(), "");
+        assert_eq!(to_csv("[{}]", OutputFormat::Json).unwrap(), "");
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{self, Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use clap::Parser;
+use rcli::{convert_csv, CsvOpts};
+
+/// Tracks the live and peak heap usage of the test binary
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Produces a large CSV on the fly, so the input itself never sits in memory
+struct GeneratedCsv {
+    rows: usize,
+    next: usize,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl GeneratedCsv {
+    fn new(rows: usize) -> Self {
+        let line = b"Name,Position,DOB,Nationality,Kit Number\n".to_vec();
+        GeneratedCsv { rows, next: 0, line, pos: 0 }
+    }
+}
+
+impl Read for GeneratedCsv {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            if self.next == self.rows {
+                return Ok(0);
+            }
+            self.line.clear();
+            let i = self.next;
+            writeln!(self.line, "Player {i},Midfield,\"Jan {}, 1990 (29)\",Country {},{}", i % 28 + 1, i % 50, i % 99)?;
+            self.next += 1;
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Counts bytes written and throws them away
+struct CountingSink(usize);
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_convert_large_csv_under_memory_ceiling() -> anyhow::Result<()> {
+    const ROWS: usize = 200_000;
+    const CEILING: usize = 4 * 1024 * 1024;
+
+    for format in ["json", "yaml"] {
+        let opts = CsvOpts::parse_from(["csv", "-i", "-", "--format", format]);
+        let baseline = ALLOCATED.load(Ordering::SeqCst);
+        PEAK.store(baseline, Ordering::SeqCst);
+
+        let mut sink = CountingSink(0);
+        convert_csv(GeneratedCsv::new(ROWS), &mut sink, &opts)?;
+
+        let peak = PEAK.load(Ordering::SeqCst) - baseline;
+        // Output alone is several times the ceiling, so buffering it would fail the check
+        assert!(sink.0 > 5 * CEILING, "{format}: only {} bytes written", sink.0);
+        assert!(peak < CEILING, "{format}: peak heap usage {peak} bytes exceeds {CEILING}");
+    }
+    Ok(())
+}